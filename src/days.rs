@@ -22,4 +22,14 @@ pub mod day_21;
 pub mod day_22;
 pub mod day_23;
 pub mod day_24;
-pub mod day_25;
\ No newline at end of file
+pub mod day_25;
+
+
+// Reads the command line argument at index, falling back to default
+// when it is missing or can't be parsed
+pub fn arg_or<T: std::str::FromStr>(index: usize, default: T) -> T {
+    std::env::args()
+        .nth(index)
+        .and_then(|a| a.parse().ok())
+        .unwrap_or(default)
+}
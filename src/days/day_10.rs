@@ -2,171 +2,631 @@ use std::fs;
 use std::io::BufReader;
 use std::io::prelude::*;
 use std::collections::HashMap;
+use std::fmt;
 
 use crate::d10::Instruction::*;
 
+#[derive(Clone, Copy)]
 enum Instruction {
     Nop,
-    Add(i32),
+    Add(char, i32),
+    Set(char, i32),
+    Jump(i32),
+    JumpNotZero(char, i32),
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Opcode {
+    Nop,
+    Add,
+    Set,
+    Jump,
+    JumpNotZero,
+}
+
+// Number of cycles each kind of instruction takes to complete
+struct InstructionSet {
+    costs: HashMap<Opcode, u32>,
+}
+
+// Registers are named 'a' to 'z', only 'x' starts non zero
+struct Registers([i32; 26]);
+
+enum Breakpoint {
+    Cycle(u32),
+    Register(char, i32),
+}
+
+enum StopReason {
+    Halted,
+    Breakpoint(usize),
+}
+
+struct Cpu {
+    program: Vec<Instruction>,
+    instruction_set: InstructionSet,
+    registers: Registers,
+    breakpoints: Vec<Breakpoint>,
+    pc: usize,
+    cycle: u32,
+    cycles_to_complete: u32,
+    halted: bool,
+}
+
+// Hooks into the CPU. on_cycle sees the registers during a cycle, before
+// the instruction being executed has taken effect
+trait Observer {
+    fn on_cycle(&mut self, cycle: u32, registers: &Registers);
+
+    fn on_instruction(&mut self, _cycle: u32, _instruction: &Instruction, _finished: bool) {}
+}
+
+struct SignalStrength {
+    samples: HashMap<u32, i32>,
+}
+
+struct Crt {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+struct Tracer;
+
+// Records each executed instruction with the cycles it ran for
+struct Listing {
+    start: u32,
+    x: i32,
+    lines: Vec<String>,
+}
+
+// Standard 4x6 capital letter font, glyphs are 5 pixels apart on the CRT
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+const GLYPH_SPACING: usize = 5;
+
+const FONT: [(char, [&str; GLYPH_HEIGHT]); 17] = [
+    ('A', [".##.", "#..#", "#..#", "####", "#..#", "#..#"]),
+    ('B', ["###.", "#..#", "###.", "#..#", "#..#", "###."]),
+    ('C', [".##.", "#..#", "#...", "#...", "#..#", ".##."]),
+    ('E', ["####", "#...", "###.", "#...", "#...", "####"]),
+    ('F', ["####", "#...", "###.", "#...", "#...", "#..."]),
+    ('G', [".##.", "#..#", "#...", "#.##", "#..#", ".###"]),
+    ('H', ["#..#", "#..#", "####", "#..#", "#..#", "#..#"]),
+    ('I', [".###", "..#.", "..#.", "..#.", "..#.", ".###"]),
+    ('J', ["..##", "...#", "...#", "...#", "#..#", ".##."]),
+    ('K', ["#..#", "#.#.", "##..", "#.#.", "#.#.", "#..#"]),
+    ('L', ["#...", "#...", "#...", "#...", "#...", "####"]),
+    ('O', [".##.", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('P', ["###.", "#..#", "#..#", "###.", "#...", "#..."]),
+    ('R', ["###.", "#..#", "#..#", "###.", "#.#.", "#..#"]),
+    ('S', [".###", "#...", "#...", ".##.", "...#", "###."]),
+    ('U', ["#..#", "#..#", "#..#", "#..#", "#..#", ".##."]),
+    ('Z', ["####", "...#", "..#.", ".#..", "#...", "####"]),
+];
+
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
     let file = fs::File::open(file_name)
         .expect("File not found");
     let reader = BufReader::new(file);
-    let mut lines = reader.lines();
 
-    let mut signal_strength_cycles = HashMap::from([
-        (20, 0), (60, 0), (100, 0), (140, 0), (180, 0), (220, 0)
-    ]);
+    let mut program = Vec::new();
 
-    let mut screen = Vec::new();
+    for (i, l) in reader.lines().enumerate() {
+        let line = l?;
+        let instruction = parse_instruction(&line)
+            .unwrap_or_else(|| panic!("Invalid instruction on line {}: {}", i + 1, line));
+        program.push(instruction);
+    }
 
+    let mut instruction_set = InstructionSet::default();
+    let mut breakpoints = Vec::new();
+    let mut trace = false;
+    let mut disasm = false;
+
+    // Usage: cargo run -- 10 [trace] [disasm] [assemble IMAGE]
+    //                        [cost OPCODE CYCLES]... [break-cycle CYCLE]...
+    //                        [break-reg REG VALUE]...
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut next = || args.next().expect("Missing argument");
+
+        match arg.as_str() {
+            "trace" => trace = true,
+            "disasm" => disasm = true,
+            "assemble" => return assemble_image(next()),
+            "cost" => {
+                let opcode = parse_opcode(next()).expect("Unknown opcode");
+                instruction_set.costs.insert(opcode, next().parse().unwrap());
+            },
+            "break-cycle" => breakpoints.push(Breakpoint::Cycle(next().parse().unwrap())),
+            "break-reg" => {
+                let reg = next().chars().next().unwrap();
+                breakpoints.push(Breakpoint::Register(reg, next().parse().unwrap()));
+            },
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
 
-    let mut current_cycle = 1;
-    let mut reg_x = 1;
-    let mut current_instr = None;
-    let mut cycles_to_complete = 0; 
-    let mut prev_cycle = 0;
+    let mut cpu = Cpu::new(program, instruction_set);
+    cpu.breakpoints = breakpoints;
 
+    let mut signal = SignalStrength::new(&[20, 60, 100, 140, 180, 220]);
+    let mut crt = Crt::new(40, 6);
+    let mut tracer = Tracer;
+    let mut listing = Listing { start: 0, x: 0, lines: Vec::new() };
 
     loop {
-        print!("c {}, x {}: ", current_cycle, reg_x);
+        let mut observers: Vec<&mut dyn Observer> = vec![&mut signal, &mut crt];
+        if trace {
+            observers.push(&mut tracer);
+        }
+        if disasm {
+            observers.push(&mut listing);
+        }
 
-        // Update Screen State
-        if prev_cycle != current_cycle {
-            let sprint_start_pos = reg_x - 1; // reg_x points to middle of sprite
-            let pixel_row_pos = (current_cycle - 1) % 40;
-            let diff = pixel_row_pos - sprint_start_pos;
-            let pixel = if diff >= 0 && diff < 3 {'#'} else {'.'};
-            
-            screen.push(pixel);
-            prev_cycle = current_cycle;
+        match cpu.run(&mut observers) {
+            StopReason::Halted => break,
+            StopReason::Breakpoint(i) => {
+                println!("breakpoint {} hit at cycle {}", i, cpu.cycle);
+                cpu.print_state();
+            },
         }
+    }
 
+    for line in &listing.lines {
+        println!("{}", line);
+    }
 
-        if let Some(val) = signal_strength_cycles.get_mut(&current_cycle) {
-            *val = reg_x;
+    println!("\nX: {}, Cycle: {}", cpu.registers.get('x'), cpu.cycle);
+    println!("part one: {}\n", signal.total());
+    print!("{}", crt.render());
+    println!("part two: {}", crt.decode());
+
+    Ok(())
+}
+
+
+
+/* CPU */
+impl Default for InstructionSet {
+    fn default() -> InstructionSet {
+        InstructionSet {
+            costs: HashMap::from([
+                (Opcode::Nop, 1),
+                (Opcode::Add, 2),
+                (Opcode::Set, 1),
+                (Opcode::Jump, 1),
+                (Opcode::JumpNotZero, 1),
+            ]),
         }
+    }
+}
 
-        // Update CPU State 
-        if cycles_to_complete != 0 && !current_instr.is_none() {
-            println!("exec");
-            current_cycle += 1;
-            cycles_to_complete -= 1;
-            continue;
+impl InstructionSet {
+    fn cost(&self, instruction: &Instruction) -> u32 {
+        self.costs.get(&instruction.opcode()).copied().unwrap_or(1).max(1)
+    }
+}
+
+impl Registers {
+    fn new() -> Registers {
+        let mut registers = Registers([0; 26]);
+        registers.set('x', 1);
+        registers
+    }
+
+    fn get(&self, name: char) -> i32 {
+        self.0[register_index(name)]
+    }
+
+    fn set(&mut self, name: char, value: i32) {
+        self.0[register_index(name)] = value;
+    }
+}
+
+fn register_index(name: char) -> usize {
+    assert!(name.is_ascii_lowercase(), "Invalid register {}", name);
+    name as usize - 'a' as usize
+}
+
+impl Cpu {
+    fn new(program: Vec<Instruction>, instruction_set: InstructionSet) -> Cpu {
+        Cpu {
+            program,
+            instruction_set,
+            registers: Registers::new(),
+            breakpoints: Vec::new(),
+            pc: 0,
+            cycle: 1,
+            cycles_to_complete: 0,
+            halted: false,
         }
+    }
 
-        current_instr = match current_instr {
-            Some(Add(x)) => {
-                /* Complete add instruction */
-                reg_x += x;
-                println!("finished");
-                current_cycle += 1;
-                None
-            },
-            Some(Nop) => {
-                /* Complete Noop */
-                println!("finished");
-                current_cycle += 1;
-                None
+    // Runs a single cycle, returning false once the program has finished
+    fn step(&mut self, observers: &mut [&mut dyn Observer]) -> bool {
+        if self.halted || self.pc >= self.program.len() {
+            self.halted = true;
+            return false;
+        }
+
+        let instruction = self.program[self.pc];
+
+        if self.cycles_to_complete == 0 {
+            self.cycles_to_complete = self.instruction_set.cost(&instruction);
+            for o in observers.iter_mut() {
+                o.on_instruction(self.cycle, &instruction, false);
+            }
+        }
+
+        for o in observers.iter_mut() {
+            o.on_cycle(self.cycle, &self.registers);
+        }
+
+        self.cycles_to_complete -= 1;
+
+        if self.cycles_to_complete == 0 {
+            self.execute(instruction);
+            for o in observers.iter_mut() {
+                o.on_instruction(self.cycle, &instruction, true);
+            }
+        }
+
+        self.cycle += 1;
+        true
+    }
+
+    // Runs until the program halts or a breakpoint is hit. Always makes
+    // progress so calling run again resumes from a breakpoint
+    fn run(&mut self, observers: &mut [&mut dyn Observer]) -> StopReason {
+        if !self.step(observers) {
+            return StopReason::Halted;
+        }
+
+        loop {
+            if let Some(i) = self.breakpoints.iter().position(|b| self.at_breakpoint(b)) {
+                return StopReason::Breakpoint(i);
+            }
+
+            if !self.step(observers) {
+                return StopReason::Halted;
+            }
+        }
+    }
+
+    fn at_breakpoint(&self, breakpoint: &Breakpoint) -> bool {
+        match breakpoint {
+            Breakpoint::Cycle(cycle) => self.cycle == *cycle,
+            Breakpoint::Register(name, value) => self.registers.get(*name) == *value,
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) {
+        let mut next_pc = self.pc as i64 + 1;
+
+        match instruction {
+            Nop => (),
+            Add(reg, x) => self.registers.set(reg, self.registers.get(reg) + x),
+            Set(reg, x) => self.registers.set(reg, x),
+            Jump(offset) => next_pc = self.pc as i64 + offset as i64,
+            JumpNotZero(reg, offset) => if self.registers.get(reg) != 0 {
+                next_pc = self.pc as i64 + offset as i64;
             },
-            None => {
-                /* Parse next instruction */
-                let line = lines.next();
-
-                if line.is_none() {
-                    /* Finished */
-                    println!("end");
-                    break;
-                }
-                
-                let next = parse_instruction(&line.unwrap()?).unwrap();
+        }
+
+        if next_pc < 0 {
+            self.halted = true;
+        } else {
+            self.pc = next_pc as usize;
+        }
+    }
+
+    fn print_state(&self) {
+        print!("cycle {}, pc {}:", self.cycle, self.pc);
+        for (i, value) in self.registers.0.iter().enumerate() {
+            if *value != 0 {
+                print!(" {}={}", (b'a' + i as u8) as char, value);
+            }
+        }
+        println!();
+    }
+}
+
+
+
+/* Observers */
+impl SignalStrength {
+    fn new(cycles: &[u32]) -> SignalStrength {
+        SignalStrength {
+            samples: cycles.iter().map(|c| (*c, 0)).collect(),
+        }
+    }
 
-                print!("start ");
-                next.print();
+    fn total(&self) -> i32 {
+        self.samples.iter()
+            .map(|(cycle, x)| *cycle as i32 * x)
+            .sum()
+    }
+}
 
-                cycles_to_complete = match next {
-                    Nop => 0,
-                    Add(_) => 1,
-                };
+impl Observer for SignalStrength {
+    fn on_cycle(&mut self, cycle: u32, registers: &Registers) {
+        if let Some(val) = self.samples.get_mut(&cycle) {
+            *val = registers.get('x');
+        }
+    }
+}
 
-                Some(next)
+impl Crt {
+    fn new(width: usize, height: usize) -> Crt {
+        Crt {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut output = String::new();
+
+        for row in self.pixels.chunks(self.width) {
+            for pixel in row {
+                output.push(if *pixel { '#' } else { '.' });
             }
-        };
+            output.push('\n');
+        }
+
+        output
+    }
+
+    // Reads the screen as a line of letters, unknown glyphs become '?'
+    fn decode(&self) -> String {
+        let glyphs = (self.width + GLYPH_SPACING - GLYPH_WIDTH) / GLYPH_SPACING;
+
+        (0..glyphs)
+            .map(|g| self.decode_glyph(g * GLYPH_SPACING))
+            .collect()
     }
 
-    println!("\nX: {}, Cycle: {}", reg_x, current_cycle);
+    fn decode_glyph(&self, start_col: usize) -> char {
+        if self.height < GLYPH_HEIGHT {
+            return '?';
+        }
 
-    let mut signal_strength = 0;
+        let lit = |row: usize, col: usize| self.pixels[row * self.width + start_col + col];
 
-    for (cycle, reg) in signal_strength_cycles.iter() {
-        println!("c: {}, x: {}", cycle, reg);
-        signal_strength += cycle * reg;
+        FONT.iter()
+            .find(|(_, rows)| rows.iter().enumerate().all(|(row, pattern)| {
+                pattern.chars().enumerate().all(|(col, ch)| (ch == '#') == lit(row, col))
+            }))
+            .map(|(letter, _)| *letter)
+            .unwrap_or('?')
     }
+}
 
-    println!("sterngth: {}\n", signal_strength);
+impl Observer for Crt {
+    fn on_cycle(&mut self, cycle: u32, registers: &Registers) {
+        // reg_x points to the middle of the 3 pixel wide sprite
+        let index = (cycle as usize - 1) % (self.width * self.height);
+        let col = (index % self.width) as i32;
 
-    for (i, pixel) in screen.iter().enumerate() {
-        if i % 40 == 0 {
-            print!("Cycle ");
+        if (col - registers.get('x')).abs() <= 1 {
+            self.pixels[index] = true;
+        }
+    }
+}
 
-            if i < 10 { print!("  "); }
-            else if i < 100 { print!(" "); }
+impl Observer for Tracer {
+    fn on_cycle(&mut self, cycle: u32, registers: &Registers) {
+        println!("c {}, x {}", cycle, registers.get('x'));
+    }
 
-            print!("{} -> ", i);
+    fn on_instruction(&mut self, cycle: u32, instruction: &Instruction, finished: bool) {
+        println!("c {}: {} {}", cycle, if finished { "finished" } else { "start" }, instruction);
+    }
+}
+
+impl Observer for Listing {
+    fn on_cycle(&mut self, _cycle: u32, registers: &Registers) {
+        self.x = registers.get('x');
+    }
+
+    fn on_instruction(&mut self, cycle: u32, instruction: &Instruction, finished: bool) {
+        if !finished {
+            self.start = cycle;
+            return;
         }
 
-        print!("{}", pixel);
+        let cycles = format!("{}-{}", self.start, cycle);
+        self.lines.push(format!("{:>8}  x={:<4} {}", cycles, self.x, instruction));
+    }
+}
+
+
 
-        if i % 40 == 39 {
-            println!(" <- cycke {}", i);
+/* Assembler */
+fn assemble_image(image_file: &str) -> std::io::Result<()> {
+    let file = fs::File::open(image_file)
+        .expect("File not found");
+    let reader = BufReader::new(file);
 
-            if i == 239 {break;}
+    let mut width = 0;
+    let mut image = Vec::new();
+
+    for (row, l) in reader.lines().enumerate() {
+        let line = l?;
+
+        if line.is_empty() {
+            continue;
         }
+
+        if width == 0 {
+            width = line.len();
+        } else if line.len() != width {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}: expected {} pixels, found {}", row + 1, width, line.len())
+            ));
+        }
+
+        image.extend(line.chars().map(|ch| ch == '#'));
     }
 
-    println!();
+    if image.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData, "empty image"
+        ));
+    }
+
+    let program = match assemble(&image, width) {
+        Some(program) => program,
+        None => {
+            println!("image can't be drawn by a noop/addx program");
+            return Ok(());
+        }
+    };
+
+    for instruction in &program {
+        println!("{}", instruction);
+    }
+
+    // Check the program really draws the image
+    let mut cpu = Cpu::new(program, InstructionSet::default());
+    let mut crt = Crt::new(width, image.len() / width);
+    cpu.run(&mut [&mut crt]);
+
+    println!("verified: {}", crt.pixels == image);
 
     Ok(())
 }
 
+// Finds a noop/addx program whose CRT output is exactly the image. X only
+// changes when an addx completes, so this searches forward over (cycle, X)
+// states, trying addx before noop to keep the program short
+fn assemble(image: &[bool], width: usize) -> Option<Vec<Instruction>> {
+    let cycles = image.len();
+
+    // Any X outside this range leaves the whole row dark
+    let min_x = -2;
+    let max_x = width as i32 + 1;
+    let x_count = (max_x - min_x + 1) as usize;
+
+    let fits = |cycle: usize, x: i32| {
+        let col = (cycle % width) as i32;
+        ((col - x).abs() <= 1) == image[cycle]
+    };
+
+    // reached_from[cycle][x] is the state the program was in before the
+    // instruction that leaves X at x at the start of cycle
+    let mut reached_from = vec![vec![None; x_count]; cycles + 1];
+    reached_from[0][(1 - min_x) as usize] = Some((0, 1));
+
+    for cycle in 0..cycles {
+        for x in min_x..=max_x {
+            if reached_from[cycle][(x - min_x) as usize].is_none() || !fits(cycle, x) {
+                continue;
+            }
+
+            if cycle + 1 < cycles && fits(cycle + 1, x) {
+                for next_x in min_x..=max_x {
+                    let state = &mut reached_from[cycle + 2][(next_x - min_x) as usize];
+                    if state.is_none() {
+                        *state = Some((cycle, x));
+                    }
+                }
+            }
+
+            let state = &mut reached_from[cycle + 1][(x - min_x) as usize];
+            if state.is_none() {
+                *state = Some((cycle, x));
+            }
+        }
+    }
+
+    // Walk back from any finishing state
+    let mut x = (min_x..=max_x).find(|x| reached_from[cycles][(x - min_x) as usize].is_some())?;
+    let mut cycle = cycles;
+    let mut program = Vec::new();
+
+    while cycle > 0 {
+        let (prev_cycle, prev_x) = reached_from[cycle][(x - min_x) as usize]?;
+
+        program.push(if cycle - prev_cycle == 1 { Nop } else { Add('x', x - prev_x) });
+
+        cycle = prev_cycle;
+        x = prev_x;
+    }
+
+    program.reverse();
+    Some(program)
+}
+
 
 
 /* Debug */
 impl Instruction {
-    fn print(&self) {
+    fn opcode(&self) -> Opcode {
         match self {
-            Nop     => println!("noop"),
-            Add(x)  => println!("addx {}", x),
-        };
+            Nop => Opcode::Nop,
+            Add(..) => Opcode::Add,
+            Set(..) => Opcode::Set,
+            Jump(_) => Opcode::Jump,
+            JumpNotZero(..) => Opcode::JumpNotZero,
+        }
     }
 }
 
-/* parsing */
-fn parse_instruction(line: &str) -> Option<Instruction> {
-    match line.chars().next()? {
-        'n' => Some(Nop),
-        'a' => parse_add(line),
-        _   => None
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Nop                         => write!(f, "noop"),
+            Add(reg, x)                 => write!(f, "add{} {}", reg, x),
+            Set(reg, x)                 => write!(f, "set{} {}", reg, x),
+            Jump(offset)                => write!(f, "jmp {}", offset),
+            JumpNotZero(reg, offset)    => write!(f, "jnz{} {}", reg, offset),
+        }
     }
 }
 
+/* parsing */
+// Register instructions take the register as a suffix, e.g. "addx 3",
+// "sety -1" or "jnzy -2"
+fn parse_instruction(line: &str) -> Option<Instruction> {
+    let mut parts = line.split_whitespace();
+    let mnemonic = parts.next()?;
+    let mut arg = || parts.next()?.parse::<i32>().ok();
 
-fn parse_add(line: &str) -> Option<Instruction> {
-    let mut output = 0;
-    let mut sign = 1 as i32;
+    if mnemonic == "noop" {
+        return Some(Nop);
+    }
 
-    for ch in line.chars().skip(5) {
-        match ch {
-            '0'..='9' => output = output * 10 + (ch as u32 - '0' as u32),
-            '-' => sign = -1,
-            _   => break,
-        }
+    if mnemonic == "jmp" {
+        return Some(Jump(arg()?));
     }
 
+    let (name, reg) = mnemonic.split_at(mnemonic.len().checked_sub(1)?);
+    let reg = reg.chars().next().filter(|ch| ch.is_ascii_lowercase())?;
 
-    Some(Add(output as i32 * sign))
+    match name {
+        "add" => Some(Add(reg, arg()?)),
+        "set" => Some(Set(reg, arg()?)),
+        "jnz" => Some(JumpNotZero(reg, arg()?)),
+        _     => None,
+    }
 }
 
+
+fn parse_opcode(name: &str) -> Option<Opcode> {
+    match name {
+        "noop" => Some(Opcode::Nop),
+        "add"  => Some(Opcode::Add),
+        "set"  => Some(Opcode::Set),
+        "jmp"  => Some(Opcode::Jump),
+        "jnz"  => Some(Opcode::JumpNotZero),
+        _      => None,
+    }
+}
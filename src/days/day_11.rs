@@ -1,90 +1,127 @@
 use std::fs;
 use std::io::BufReader;
 use std::io::prelude::*;
+use std::fmt;
+use std::collections::HashMap;
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+use num_bigint::{BigInt, Sign};
 
 use crate::d11::OperationType::*;
-use crate::d11::Operand::*;
+use crate::d11::Expr::*;
+use crate::d11::Worry::*;
+use crate::days::arg_or;
 
 
 struct Monkey {
-    items: Vec<u64>,
+    items: Vec<Worry>,
     items_inspected: u64,
-    operation: Operation,
-    test_amount: u64,
+    operation: Expr,
+    test: Test,
     true_monkey: u64,
     false_monkey: u64,
 }
 
-struct Operation {
-    v1: Operand,
-    v2: Operand,
-    opp: OperationType,
+enum Expr {
+    Old,
+    Val(i64),
+    BinOp(Box<Expr>, OperationType, Box<Expr>),
 }
 
+#[derive(Clone, Copy)]
 enum OperationType {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
 }
 
-enum Operand {
-    Old,
-    Val(u64),
+enum Test {
+    Divisible(u64),
+    Not(Box<Test>),
+    And(Box<Test>, Box<Test>),
+    Or(Box<Test>, Box<Test>),
 }
 
+// Worry level that starts as a machine integer and is promoted to a big
+// integer when an operation would overflow
+#[derive(Clone)]
+enum Worry {
+    Small(i64),
+    Big(BigInt),
+}
 
+// An operation divided, or took a remainder, by zero
+#[derive(Debug)]
+struct DivisionByZero;
 
-pub fn run(file_name: &str) -> std::io::Result<()> {
-    let mut monkeys = parse_monkeys(file_name)?;
-    let rounds = 10000;
-    let decrease_amount = 1;
-    let mut distress = 1;
-
-    for m in &monkeys {
-        distress *= m.test_amount;
-    }
+enum Relief {
+    None,
+    Divide(i64),
+    Custom(Box<dyn Fn(Worry) -> Worry>),
+}
 
-    if decrease_amount != 1 {
-        distress = 1;
-    }
+// How worry levels are kept after each inspection. Modular keeps them
+// modulo the lcm of every test divisor, which only gives the right
+// answers when there is no relief and no division or modulo
+struct WorryPolicy {
+    relief: Relief,
+    modulus: Option<u64>,
+}
 
-    for round_i in 0..rounds {
-        for monkey_i in 0..monkeys.len() {
-            // Plays with items 
-            monkeys[monkey_i].play_with_items(decrease_amount);
-            let true_m = monkeys[monkey_i].true_monkey;
-            let false_m = monkeys[monkey_i].false_monkey;
-            let div = monkeys[monkey_i].test_amount;
 
 
-            let items = monkeys[monkey_i].items.clone();
-            monkeys[monkey_i].items.clear();
+pub fn run(file_name: &str) -> std::io::Result<()> {
+    let mut monkeys = parse_monkeys(file_name)?;
 
-            // Moves items to other monkey
-            for item in items {
-                if item % div == 0 {
-                    monkeys[true_m as usize].items.push(item % distress);
-                } else {
-                    monkeys[false_m as usize].items.push(item % distress);
-                }
-            }
-        }
+    // Usage: cargo run -- 11 [rounds] [none | divN | sqrt] [auto | exact | modular]
+    //                        [simulate | predict]
+    let rounds: u64 = arg_or(2, 10000);
+    let relief = match arg_or(3, String::from("none")).as_str() {
+        "none" => Relief::None,
+        // Negative worry keeps its sign, so sqrt of -w is -sqrt(w)
+        "sqrt" => Relief::Custom(Box::new(|w| {
+            let w = w.to_big();
+            Big(if w.sign() == Sign::Minus { -(-w).sqrt() } else { w.sqrt() })
+        })),
+        r => match r.strip_prefix("div").and_then(|n| n.parse().ok()) {
+            Some(0) => panic!("Relief can't divide by zero"),
+            Some(n) => Relief::Divide(n),
+            None => panic!("Unknown relief {}", r),
+        },
+    };
+
+    let policy = WorryPolicy::new(relief, &arg_or(4, String::from("auto")), &monkeys);
+
+    match policy.modulus {
+        Some(m) => println!("modular mode, modulus {}", m),
+        None => println!("exact mode"),
+    }
 
-        println!("After round: {}", round_i);
-        for (i, monkey) in monkeys.iter().enumerate() {
-            println!(
-                "Monkey {} inspected {} items: {:?}", 
-                i, monkey.items_inspected, monkey.items);
+    // Predicting from item cycles needs bounded worry levels
+    let predict = match arg_or(5, String::new()).as_str() {
+        "simulate" => false,
+        "predict" if policy.modulus.is_none() =>
+            panic!("Predicting needs modular mode"),
+        _ => policy.modulus.is_some(),
+    };
+
+    let inspections = if predict {
+        predict_inspections(&monkeys, &policy, rounds)?
+    } else {
+        for _ in 0..rounds {
+            play_round(&mut monkeys, &policy)?;
         }
-        println!();
-    }
+        monkeys.iter().map(|m| m.items_inspected).collect()
+    };
 
-    let mut top_1 = 0;
-    let mut top_2 = 0;
+    let mut top_1: u64 = 0;
+    let mut top_2: u64 = 0;
 
-    for (i, monkey) in monkeys.iter().enumerate() {
-        let items = monkey.items_inspected;
+    for (i, items) in inspections.into_iter().enumerate() {
         println!("Monkey {} inspected {} items", i, items);
 
         if items > top_1 {
@@ -95,90 +132,359 @@ pub fn run(file_name: &str) -> std::io::Result<()> {
         }
     }
 
-    println!("T1: {}, T2: {}, S: {}", top_1, top_2, top_1 * top_2);
+    println!("T1: {}, T2: {}, S: {}", top_1, top_2, top_1 as u128 * top_2 as u128);
 
     Ok(())
 }
 
 /* Problem */
+fn play_round(monkeys: &mut [Monkey], policy: &WorryPolicy) -> std::io::Result<()> {
+    for monkey_i in 0..monkeys.len() {
+        let items = std::mem::take(&mut monkeys[monkey_i].items);
+        monkeys[monkey_i].items_inspected += items.len() as u64;
+
+        for item in items {
+            let (target, item) = monkeys[monkey_i].inspect(&item, policy)
+                .map_err(|_| division_error(monkey_i))?;
+            monkeys[target].items.push(item);
+        }
+    }
+
+    Ok(())
+}
+
+fn division_error(monkey_i: usize) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("monkey {} divided by zero", monkey_i)
+    )
+}
+
+impl Monkey {
+    // New worry level of an item and the monkey it is thrown to
+    fn inspect(&self, item: &Worry, policy: &WorryPolicy) -> Result<(usize, Worry), DivisionByZero> {
+        let item = policy.apply(self.operation.eval(item)?)?;
+
+        let target = if self.test.holds(&item) {
+            self.true_monkey
+        } else {
+            self.false_monkey
+        };
+
+        Ok((target as usize, item))
+    }
+}
+
+
+/* Cycle Detection */
+// Items never affect each other so each can be followed on its own. In
+// modular mode an item's state at the start of a round, (monkey, worry),
+// has to repeat eventually, after which its inspections repeat too
+fn predict_inspections(
+    monkeys: &[Monkey], policy: &WorryPolicy, rounds: u64
+) -> std::io::Result<Vec<u64>> {
+    let mut totals = vec![0; monkeys.len()];
+    let mut longest_cycle = 0;
+
+    for (monkey_i, monkey) in monkeys.iter().enumerate() {
+        for item in &monkey.items {
+            let (counts, cycle_len) = predict_item(monkeys, policy, monkey_i, item, rounds)?;
+            longest_cycle = longest_cycle.max(cycle_len);
+
+            for (total, count) in totals.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+    }
+
+    println!("longest item cycle: {} rounds", longest_cycle);
+    Ok(totals)
+}
+
+// Inspections made by each monkey on one item over the given rounds,
+// along with the length of the item's cycle (0 if none was reached)
+fn predict_item(
+    monkeys: &[Monkey], policy: &WorryPolicy,
+    start_monkey: usize, worry: &Worry, rounds: u64
+) -> std::io::Result<(Vec<u64>, u64)> {
+    let modulus = policy.modulus.expect("Predicting needs modular mode");
+    let mut seen: HashMap<(usize, u64), usize> = HashMap::new();
+
+    // history[r] is the inspections made by each monkey in the first r rounds
+    let mut history = vec![vec![0; monkeys.len()]];
+    let mut monkey_i = start_monkey;
+    let mut worry = worry.clone();
+
+    for round in 0..rounds as usize {
+        let state = (monkey_i, worry.rem(modulus));
+
+        if let Some(&cycle_start) = seen.get(&state) {
+            let cycle_len = round - cycle_start;
+            let remaining = rounds - round as u64;
+            let full_cycles = remaining / cycle_len as u64;
+            let rest = (remaining % cycle_len as u64) as usize;
+
+            let counts = (0..monkeys.len())
+                .map(|m| {
+                    let per_cycle = history[round][m] - history[cycle_start][m];
+                    let partial = history[cycle_start + rest][m] - history[cycle_start][m];
+                    history[round][m] + full_cycles * per_cycle + partial
+                })
+                .collect();
+
+            return Ok((counts, cycle_len as u64));
+        }
+
+        seen.insert(state, round);
+        let mut counts = history[round].clone();
+
+        // Thrown to a later monkey means being inspected again this round
+        loop {
+            counts[monkey_i] += 1;
+            let (target, new_worry) = monkeys[monkey_i].inspect(&worry, policy)
+                .map_err(|_| division_error(monkey_i))?;
+            worry = new_worry;
+
+            let round_done = target <= monkey_i;
+            monkey_i = target;
+
+            if round_done {
+                break;
+            }
+        }
+
+        history.push(counts);
+    }
+
+    Ok((history.pop().unwrap(), 0))
+}
+
+impl WorryPolicy {
+    fn new(relief: Relief, mode: &str, monkeys: &[Monkey]) -> WorryPolicy {
+        let can_be_modular = matches!(relief, Relief::None) &&
+            monkeys.iter().all(|m| !m.operation.uses_division());
+
+        let modular = match mode {
+            "exact" => false,
+            "modular" if !can_be_modular =>
+                panic!("Modular mode needs no relief and no division or modulo"),
+            "modular" => true,
+            _ => can_be_modular,
+        };
+
+        let modulus = if modular {
+            let mut divisors = Vec::new();
+            for m in monkeys {
+                m.test.divisors(&mut divisors);
+            }
+            Some(divisors.into_iter().fold(1, lcm))
+        } else {
+            None
+        };
+
+        WorryPolicy { relief, modulus }
+    }
+
+    fn apply(&self, worry: Worry) -> Result<Worry, DivisionByZero> {
+        let worry = match &self.relief {
+            Relief::None => worry,
+            Relief::Divide(n) => worry.apply(&Small(*n), &Div)?,
+            Relief::Custom(f) => f(worry),
+        };
+
+        Ok(match self.modulus {
+            Some(m) => Small(worry.rem(m) as i64),
+            None => worry,
+        })
+    }
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+fn lcm(a: u64, b: u64) -> u64 {
+    let l = a / gcd(a, b);
+    l.checked_mul(b).expect("Test divisors lcm overflows")
+}
+
+impl Worry {
+    fn to_big(&self) -> BigInt {
+        match self {
+            Small(v) => BigInt::from(*v),
+            Big(v) => v.clone(),
+        }
+    }
+
+    fn from_big(v: BigInt) -> Worry {
+        match i64::try_from(&v) {
+            Ok(v) => Small(v),
+            Err(_) => Big(v),
+        }
+    }
+
+    // Division and remainder by zero are errors rather than overflows
+    fn apply(&self, other: &Worry, opp: &OperationType) -> Result<Worry, DivisionByZero> {
+        if matches!(opp, Div | Mod) && other.is_zero() {
+            return Err(DivisionByZero);
+        }
+
+        if let (Small(a), Small(b)) = (self, other) {
+            let checked = match opp {
+                Add => a.checked_add(*b),
+                Sub => a.checked_sub(*b),
+                Mul => a.checked_mul(*b),
+                Div => a.checked_div(*b),
+                Mod => a.checked_rem_euclid(*b),
+            };
+
+            if let Some(v) = checked {
+                return Ok(Small(v));
+            }
+        }
+
+        // Overflowed, redo the operation exactly
+        let (a, b) = (self.to_big(), other.to_big());
+
+        Ok(Worry::from_big(match opp {
+            Add => a + b,
+            Sub => a - b,
+            Mul => a * b,
+            Div => a / b,
+            Mod => {
+                let m = BigInt::from(b.magnitude().clone());
+                ((a % &m) + &m) % m
+            },
+        }))
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Small(v) => *v == 0,
+            Big(v) => v.sign() == Sign::NoSign,
+        }
+    }
+
+    // Non negative remainder
+    fn rem(&self, m: u64) -> u64 {
+        match self {
+            Small(v) => (*v as i128).rem_euclid(m as i128) as u64,
+            Big(v) => {
+                let m = BigInt::from(m);
+                u64::try_from(((v % &m) + &m) % &m).unwrap()
+            },
+        }
+    }
+}
+
+
+impl Expr {
+    fn eval(&self, old_value: &Worry) -> Result<Worry, DivisionByZero> {
+        match self {
+            Old => Ok(old_value.clone()),
+            Val(v) => Ok(Small(*v)),
+            BinOp(v1, opp, v2) => v1.eval(old_value)?.apply(&v2.eval(old_value)?, opp),
+        }
+    }
+
+    fn uses_division(&self) -> bool {
+        match self {
+            Old | Val(_) => false,
+            BinOp(_, Div | Mod, _) => true,
+            BinOp(v1, _, v2) => v1.uses_division() || v2.uses_division(),
+        }
+    }
+}
+
+impl Test {
+    fn holds(&self, worry: &Worry) -> bool {
+        match self {
+            Test::Divisible(n) => worry.rem(*n) == 0,
+            Test::Not(t) => !t.holds(worry),
+            Test::And(a, b) => a.holds(worry) && b.holds(worry),
+            Test::Or(a, b) => a.holds(worry) || b.holds(worry),
+        }
+    }
+
+    fn divisors(&self, output: &mut Vec<u64>) {
+        match self {
+            Test::Divisible(n) => output.push(*n),
+            Test::Not(t) => t.divisors(output),
+            Test::And(a, b) | Test::Or(a, b) => {
+                a.divisors(output);
+                b.divisors(output);
+            },
+        }
+    }
+}
 
 
 /* Debug */
 impl Monkey {
     fn print(&self) {
         self.print_items();
-        self.operation.print();
+        println!("opp: {}", self.operation);
         self.print_condition();
     }
 
     fn print_items(&self) {
-        println!("items: {:?}", self.items);
+        let items: Vec<String> = self.items.iter().map(|i| i.to_string()).collect();
+        println!("items: [{}]", items.join(", "));
     }
 
     fn print_condition(&self) {
         println!(
-            "if div by {} then {} else {}", 
-            self.test_amount, self.true_monkey,
+            "if {} then {} else {}",
+            self.test, self.true_monkey,
             self.false_monkey
         );
     }
-
-    fn play_with_items(&mut self, decrease_amount: u64) {
-        self.items_inspected += self.items.len() as u64;
-
-        for item in self.items.iter_mut() {
-            *item = self.operation.perform_opp(*item) / decrease_amount;
-        }
-    }
 }
 
-impl Operation {
-    fn print(&self) {
-        print!("opp: ");
-        self.v1.print();
-        print!(" ");
-        self.opp.print();
-        print!(" ");
-        self.v2.print();
-        println!();
-    }
-
-    fn perform_opp(&self, old_value: u64) -> u64 {
-        let v1 = self.v1.get_val(old_value);
-        let v2 = self.v2.get_val(old_value);
-
-        match self.opp {
-            Add => v1 + v2,
-            Sub => v1 - v2,
-            Mul => v1 * v2,
-            Div => v1 / v2,
-        }    
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Old => write!(f, "old"),
+            Val(v) => write!(f, "{}", v),
+            BinOp(v1, opp, v2) => {
+                let sub_expr = |e: &Expr| match e {
+                    BinOp(..) => format!("({})", e),
+                    _ => e.to_string(),
+                };
+                write!(f, "{} {} {}", sub_expr(v1), opp, sub_expr(v2))
+            },
+        }
     }
 }
 
-impl OperationType {
-    fn print(&self) {
+impl fmt::Display for OperationType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Add => print!("+"),
-            Sub => print!("-"),
-            Mul => print!("*"),
-            Div => print!("/"),
+            Add => write!(f, "+"),
+            Sub => write!(f, "-"),
+            Mul => write!(f, "*"),
+            Div => write!(f, "/"),
+            Mod => write!(f, "%"),
         }
     }
 }
 
-impl Operand {
-    fn print(&self) {
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Val(v) => print!("{}", v),
-            Old => print!("old"),
+            Test::Divisible(n) => write!(f, "div by {}", n),
+            Test::Not(t) => write!(f, "not ({})", t),
+            Test::And(a, b) => write!(f, "({}) and ({})", a, b),
+            Test::Or(a, b) => write!(f, "({}) or ({})", a, b),
         }
     }
+}
 
-    fn get_val(&self, old_value: u64) -> u64 {
+impl fmt::Display for Worry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Val(v) => *v,
-            Old => old_value,
+            Small(v) => write!(f, "{}", v),
+            Big(v) => write!(f, "{}", v),
         }
     }
 }
@@ -228,14 +534,14 @@ fn parse_monkey(lines: &Vec<String>) -> Option<Monkey> {
         items: parse_items(&lines[1])?,
         items_inspected: 0,
         operation: parse_operation(&lines[2])?,
-        test_amount: parse_num(&lines[3], 21)?,
+        test: parse_test(&lines[3])?,
         true_monkey: parse_num(&lines[4], 29)?,
         false_monkey: parse_num(&lines[5], 30)?
     })
 }
 
 
-fn parse_items(line: &str) -> Option<Vec<u64>> {
+fn parse_items(line: &str) -> Option<Vec<Worry>> {
     //   Starting items: a, b, c, ...
     let mut items = Vec::new();
     let mut curr_item = 0;
@@ -243,10 +549,10 @@ fn parse_items(line: &str) -> Option<Vec<u64>> {
     for ch in line.chars().skip(18) {
         match ch {
             '0'..='9' => {
-                curr_item = curr_item * 10 + (ch as u64 - '0' as u64);
+                curr_item = curr_item * 10 + (ch as i64 - '0' as i64);
             },
             ',' => {
-                items.push(curr_item);
+                items.push(Small(curr_item));
                 curr_item = 0;
             },
             ' ' => (),
@@ -254,67 +560,165 @@ fn parse_items(line: &str) -> Option<Vec<u64>> {
         }
     }
 
-    items.push(curr_item);
+    items.push(Small(curr_item));
 
     Some(items)
 }
 
-fn parse_operation(line: &str) -> Option<Operation> {
-    //   Operation: new = (v1) (opp) (v2)
-    let chars = line.chars().collect::<Vec<char>>();
-    let mut chars_iter = chars.iter().skip(19);
+fn parse_operation(line: &str) -> Option<Expr> {
+    //   Operation: new = (expression)
+    let (_, expr) = line.split_once('=')?;
+    let mut chars = expr.chars().peekable();
 
-    let v1 = parse_operand(&mut chars_iter)?;
-    let opp = parse_opperation_type(&mut chars_iter)?;
-    let v2 = parse_operand(&mut chars_iter)?;
+    let output = parse_expr(&mut chars)?;
 
-    Some(Operation{
-        v1: v1,
-        opp: opp,
-        v2: v2,
-    })
+    skip_spaces(&mut chars);
+    if chars.peek().is_some() {
+        return None;
+    }
+
+    Some(output)
+}
+
+
+// Usual precedence: + and - bind looser than *, / and %,
+// all are left associative
+fn parse_expr(chars: &mut Peekable<Chars>) -> Option<Expr> {
+    let mut output = parse_term(chars)?;
+
+    loop {
+        skip_spaces(chars);
+
+        let opp = match chars.peek() {
+            Some('+') => Add,
+            Some('-') => Sub,
+            _ => return Some(output),
+        };
+
+        chars.next();
+        output = BinOp(Box::new(output), opp, Box::new(parse_term(chars)?));
+    }
+}
+
+fn parse_term(chars: &mut Peekable<Chars>) -> Option<Expr> {
+    let mut output = parse_factor(chars)?;
+
+    loop {
+        skip_spaces(chars);
+
+        let opp = match chars.peek() {
+            Some('*') => Mul,
+            Some('/') => Div,
+            Some('%') => Mod,
+            _ => return Some(output),
+        };
+
+        chars.next();
+        let factor = parse_factor(chars)?;
+
+        // Dividing by a literal zero can never work
+        if matches!(opp, Div | Mod) && matches!(factor, Val(0)) {
+            return None;
+        }
+
+        output = BinOp(Box::new(output), opp, Box::new(factor));
+    }
 }
 
+fn parse_factor(chars: &mut Peekable<Chars>) -> Option<Expr> {
+    skip_spaces(chars);
 
-fn parse_opperation_type<'a, I>(chars: &mut I) -> Option<OperationType> 
-where I: Iterator<Item = &'a char> {
-    let output = match chars.next()? {
-        '+' => Some(Add),
-        '-' => Some(Sub),
-        '*' => Some(Mul),
-        '/' => Some(Div),
+    match chars.next()? {
+        '(' => {
+            let output = parse_expr(chars)?;
+            skip_spaces(chars);
+            match chars.next()? {
+                ')' => Some(output),
+                _ => None,
+            }
+        },
+        '-' => Some(BinOp(Box::new(Val(0)), Sub, Box::new(parse_factor(chars)?))),
+        'o' => match (chars.next()?, chars.next()?) {
+            ('l', 'd') => Some(Old),
+            _ => None,
+        },
+        ch @ '0'..='9' => {
+            let mut val = (ch as i64) - ('0' as i64);
+
+            while let Some(ch @ '0'..='9') = chars.peek().copied() {
+                val = val.checked_mul(10)?.checked_add((ch as i64) - ('0' as i64))?;
+                chars.next();
+            }
+
+            Some(Val(val))
+        },
         _ => None,
-    }?;
+    }
+}
+
+fn skip_spaces(chars: &mut Peekable<Chars>) {
+    while let Some(' ') = chars.peek() {
+        chars.next();
+    }
+}
 
-    chars.next();
+
+fn parse_test(line: &str) -> Option<Test> {
+    //   Test: divisible by a [and | or [not] [divisible] by b]...
+    let mut words = line.split_whitespace().skip(1).peekable();
+    let output = parse_test_or(&mut words)?;
+
+    match words.next() {
+        None => Some(output),
+        Some(_) => None,
+    }
+}
+
+// "and" binds tighter than "or"
+fn parse_test_or<'a, I>(words: &mut Peekable<I>) -> Option<Test>
+where I: Iterator<Item = &'a str> {
+    let mut output = parse_test_and(words)?;
+
+    while let Some(&"or") = words.peek() {
+        words.next();
+        output = Test::Or(Box::new(output), Box::new(parse_test_and(words)?));
+    }
 
     Some(output)
 }
 
-fn parse_operand<'a, I>(chars: &mut I) -> Option<Operand> 
-where I: Iterator<Item = &'a char> {
-    let ch = chars.next()?;
+fn parse_test_and<'a, I>(words: &mut Peekable<I>) -> Option<Test>
+where I: Iterator<Item = &'a str> {
+    let mut output = parse_test_clause(words)?;
 
-    if *ch == 'o' {
-        // Old value
-        chars.next()?;
-        chars.next()?;
-        chars.next();
+    while let Some(&"and") = words.peek() {
+        words.next();
+        output = Test::And(Box::new(output), Box::new(parse_test_clause(words)?));
+    }
+
+    Some(output)
+}
+
+fn parse_test_clause<'a, I>(words: &mut Peekable<I>) -> Option<Test>
+where I: Iterator<Item = &'a str> {
+    let mut word = words.next()?;
 
-        return Some(Old);
+    if word == "not" {
+        return Some(Test::Not(Box::new(parse_test_clause(words)?)));
     }
 
-    // Numeric value 
-    let mut val = (*ch as u64) - ('0' as u64);
+    if word == "divisible" {
+        word = words.next()?;
+    }
 
-    while let Some(ch) = chars.next() {
-        match ch {
-            '0'..='9' => val = val * 10 + (*ch as u64) - ('0' as u64),
-            _ => break,
-        }
+    if word != "by" {
+        return None;
     }
 
-    Some(Val(val))
+    match words.next()?.parse().ok()? {
+        0 => None,
+        n => Some(Test::Divisible(n)),
+    }
 }
 
 
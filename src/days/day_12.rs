@@ -3,8 +3,8 @@ use std::io::BufReader;
 use std::io::prelude::*;
 use std::collections::VecDeque;
 
-static START_VAL: u32 = 26;
-static END_VAL: u32 = 27;
+const START_VAL: u32 = 26;
+const END_VAL: u32 = 27;
 
 type NodeIndex = usize;
 type EdgeIndex = usize;
@@ -23,6 +23,19 @@ struct EdgeData {
     next_outgoing_edge: Option<EdgeIndex>,
 }
 
+// Which neighbouring positions can be walked to
+struct ClimbRules {
+    max_climb: u32,
+    max_descent: u32,
+    diagonal: bool,
+}
+
+struct Search {
+    previous: Vec<Option<NodeIndex>>,
+    distances: Vec<Option<u32>>,
+    found: Option<NodeIndex>,
+}
+
 struct Successors<'graph> {
     graph: &'graph Graph,
     currend_edge_index: Option<EdgeIndex>,
@@ -30,156 +43,185 @@ struct Successors<'graph> {
 
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
-    let (graph, start_index, end_index, width, height, matrix) = parse_graph(file_name)?;
+    let mut rules = ClimbRules { max_climb: 1, max_descent: u32::MAX, diagonal: false };
+    let mut debug = false;
+    let mut heatmap = false;
+
+    // Usage: cargo run -- 12 [path] [heatmap] [diagonal]
+    //                        [climb MAX] [descend MAX]
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut next = || args.next().expect("Missing argument").parse().unwrap();
+
+        match arg.as_str() {
+            "path" => debug = true,
+            "heatmap" => heatmap = true,
+            "diagonal" => rules.diagonal = true,
+            "climb" => rules.max_climb = next(),
+            "descend" => rules.max_descent = next(),
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
 
-    let mut distances = Vec::new();
+    let (graph, start_index, end_index, width, height, matrix) = parse_graph(file_name, &rules)?;
 
-    distances.push(find_shortest_path(&graph, start_index, end_index, width, height, false).unwrap());
+    match shortest_path(&graph, &[start_index], |i| i == end_index) {
+        Some(path) => {
+            println!("part one: {}", path.len() - 1);
+            if debug {
+                print_path(&path, &matrix, width, height);
+            }
+        },
+        None => println!("part one: no soln"),
+    }
 
-    for (i, v) in matrix.iter().enumerate() {
-        if *v != 0 {
-            continue;
-        }
+    // With every edge reversed one search from the end finds the
+    // closest of all the lowest points
+    let reversed = graph.reversed();
 
-        if let Some(distance) = find_shortest_path(&graph, i, end_index, width, height, false) {
-            distances.push(distance);
-        } else {
-            println!("no soln");
-        }
+    match shortest_path(&reversed, &[end_index], |i| elevation(matrix[i]) == 0) {
+        Some(mut path) => {
+            path.reverse();
+            println!("part two: {}", path.len() - 1);
+            if debug {
+                print_path(&path, &matrix, width, height);
+            }
+        },
+        None => println!("part two: no soln"),
     }
 
-    println!("min: {}", distances.iter().min().unwrap());
+    if heatmap {
+        // Distance from every position to the end
+        let search = bfs(&reversed, &[end_index], |_| false);
+        print_heatmap(&search.distances, width, height);
+    }
 
     Ok(())
 }
 
-fn find_shortest_path(
-    graph: &Graph, start_index: NodeIndex, end_index: NodeIndex, 
-    width: u32, height: u32, debug: bool
-) -> Option<u32> {
-    let mut distances = vec![u32::MAX; graph.nodes.len()];
-    let mut previous = vec![None as Option<NodeIndex>; graph.nodes.len()];
-    let mut visited = vec![false; graph.nodes.len()];
-
-    distances[start_index] = 0;
+// Breadth first search from every source at once, stopping at the first
+// target reached
+fn bfs<F: Fn(NodeIndex) -> bool>(
+    graph: &Graph, sources: &[NodeIndex], is_target: F
+) -> Search {
+    let mut search = Search {
+        previous: vec![None; graph.nodes.len()],
+        distances: vec![None; graph.nodes.len()],
+        found: None,
+    };
+    let mut queue = VecDeque::new();
 
-    // Dikstras search 
-    println!("start: {}", start_index);
-    while let Some(node_index) = min_node(&distances, &visited) {
-        visited[node_index] = true;
+    for source in sources {
+        search.distances[*source] = Some(0);
+        queue.push_back(*source);
+    }
 
-        if node_index == end_index || distances[node_index] == u32::MAX{
+    while let Some(node_index) = queue.pop_front() {
+        if is_target(node_index) {
+            search.found = Some(node_index);
             break;
         }
 
-        let pos = index_to_pos(node_index, width);
-
-        if debug {
-            println!("min_node: ({},{}), {}", pos.0, pos.1, distances[node_index]);
-        }
+        let distance = search.distances[node_index].unwrap() + 1;
 
         for neighbor_index in graph.successors(node_index) {
-            if visited[neighbor_index] {
+            if search.distances[neighbor_index].is_some() {
                 continue;
             }
 
-            let distance = distances[node_index] + 1;
-
-            if distance < distances[neighbor_index] {
-                // New shortest path to neightbor 
-                distances[neighbor_index] = distance;
-                previous[neighbor_index] = Some(node_index);
-            }
+            search.distances[neighbor_index] = Some(distance);
+            search.previous[neighbor_index] = Some(node_index);
+            queue.push_back(neighbor_index);
         }
     }
 
-    if !visited[end_index] {
-        return None;
-    }
+    search
+}
 
-    println!("Distance: {}", distances[end_index]);
+// Path from a source to the closest target
+fn shortest_path<F: Fn(NodeIndex) -> bool>(
+    graph: &Graph, sources: &[NodeIndex], is_target: F
+) -> Option<Vec<NodeIndex>> {
+    let search = bfs(graph, sources, is_target);
+    let mut path = vec![search.found?];
 
-    if !debug {
-        return Some(distances[end_index]);
+    while let Some(prev) = search.previous[*path.last().unwrap()] {
+        path.push(prev);
     }
 
-    let mut path = VecDeque::new();
-    let mut prev = end_index;
+    path.reverse();
+    Some(path)
+}
+
 
-    path.push_front(prev);
+// Draws the route over the height map, like in the puzzle text
+fn print_path(path: &[NodeIndex], matrix: &[u32], width: u32, height: u32) {
+    // Direction to draw at each position on the path
+    let mut steps = vec![None as Option<char>; (width * height) as usize];
+
+    for pair in path.windows(2) {
+        let (row, column) = index_to_pos(pair[0], width);
+        let (next_row, next_column) = index_to_pos(pair[1], width);
+        let row_diff: i32 = next_row as i32 - row as i32;
+        let col_diff: i32 = next_column as i32 - column as i32;
+
+        steps[pair[0]] = match (row_diff, col_diff) {
+            (0, 1) => Some('>'),
+            (0, -1) => Some('<'),
+            (-1, 0) => Some('^'),
+            (1, 0) => Some('v'),
+            (-1, 1) => Some('↗'),
+            (-1, -1) => Some('↖'),
+            (1, 1) => Some('↘'),
+            (1, -1) => Some('↙'),
+            _ => panic!("Path is not continuous: {}, {}", row_diff, col_diff),
+        };
+    }
 
-    while let Some(next) = previous[prev] {
-        path.push_front(next);
-        prev = next;
+    if let Some(last) = path.last() {
+        steps[*last] = Some('E');
     }
 
     println!("Path:");
     for row in 0..height {
         for column in 0..width {
             let index = get_index(width, row, column);
-            let in_path = path.contains(&index);
-
-            if !in_path {
-                print!(".");
-                continue;
-            } else if index == end_index {
-                print!("E");
-                continue;
-            }
-
-            let mut index_in_path = 0;
-
-            for (i, v) in path.iter().enumerate() {
-                if *v == index {
-                    index_in_path = i;
-                    break;
-                }
-            }
-
-            let (next_row, next_column) = index_to_pos(*path.get(index_in_path + 1).unwrap(), width);
-            let row_diff: i32 = next_row as i32 - row as i32;
-            let col_diff: i32 = next_column as i32 - column as i32;
-             
-            match (row_diff, col_diff) {
-                (0, 1) => print!(">"),
-                (0, -1) => print!("<"),
-                (-1, 0) => print!("^"),
-                (1, 0) => print!("v"),
-                _ => {
-                    println!("\n wtf: {}, {}", row_diff, col_diff);
-                    panic!();
-                }
-            }
+            print!("{}", steps[index].unwrap_or_else(|| height_char(matrix[index])));
         }
         println!();
-    } 
-
-    Some(distances[end_index])
+    }
 }
 
 
-fn min_node(distances: &Vec<u32>, visited: &Vec<bool>) -> Option<NodeIndex> {
-    let mut min_val = None as Option<u32>;
-    let mut min_node = None as Option<NodeIndex>;
+// Distances scaled to 0-9, '.' where the end can't be reached from
+fn print_heatmap(distances: &[Option<u32>], width: u32, height: u32) {
+    let max = distances.iter().flatten().max().copied().unwrap_or(0).max(1);
 
-    for (i, distance) in distances.iter().enumerate() {
-        if visited[i] {
-            continue;
-        }
-
-        if let Some(v) = min_val {
-            if v < *distance {
-                continue;
-            }
+    println!("Distance to end (max {}):", max);
+    for row in 0..height {
+        for column in 0..width {
+            let ch = match distances[get_index(width, row, column)] {
+                Some(d) => char::from_digit(d * 9 / max, 10).unwrap(),
+                None => '.',
+            };
+            print!("{}", ch);
         }
-        
-        min_val = Some(*distance);
-        min_node = Some(i);
+        println!();
     }
+}
+
 
-    min_node
+fn height_char(val: u32) -> char {
+    match val {
+        START_VAL => 'S',
+        END_VAL => 'E',
+        v => char::from_u32('a' as u32 + v).unwrap(),
+    }
 }
 
+
 fn index_to_pos(index: NodeIndex, width: u32) -> (u32, u32) {
     ((index as u32 / width), (index as u32 % width))
 }
@@ -206,7 +248,26 @@ impl Graph {
         node_data.first_outgoing_edge = Some(edge_index);
     }
 
-    fn successors(&self, source: NodeIndex) -> Successors {
+    fn reversed(&self) -> Graph {
+        let mut graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+        };
+
+        for _ in 0..self.nodes.len() {
+            graph.add_node();
+        }
+
+        for source in 0..self.nodes.len() {
+            for target in self.successors(source) {
+                graph.add_edge(target, source);
+            }
+        }
+
+        graph
+    }
+
+    fn successors(&self, source: NodeIndex) -> Successors<'_> {
         let first_outgoing_edge = self.nodes[source].first_outgoing_edge;
         Successors { 
             graph: self,
@@ -231,7 +292,9 @@ impl<'graph> Iterator for Successors<'graph> {
 }
 
 /* Parsing */
-fn parse_graph(file_name: &str) -> std::io::Result<(Graph, NodeIndex, NodeIndex, u32, u32, Vec<u32>)> {
+fn parse_graph(
+    file_name: &str, rules: &ClimbRules
+) -> std::io::Result<(Graph, NodeIndex, NodeIndex, u32, u32, Vec<u32>)> {
     let (matrix, width, height) = parse_as_matrix(file_name)?;
 
     let mut graph = Graph {
@@ -245,28 +308,22 @@ fn parse_graph(file_name: &str) -> std::io::Result<(Graph, NodeIndex, NodeIndex,
     // Create all nodes 
     for row in 0..height {
         for column in 0..width {
-            let ni = graph.add_node();
+            graph.add_node();
             let mi = get_index(width, row, column);
 
-            if ni != mi {
-                println!("hmmmm {} {}", mi, ni);
-            }
-
-            
             match matrix[mi] {
-                26 => start_index = mi,
-                27 => end_index = mi,
+                START_VAL => start_index = mi,
+                END_VAL => end_index = mi,
                 _ => (),
             };
         }
-    }    
-    
-    println!("Graph Form:");
+    }
+
     // Create all edges
     for row in 0..height {
         for column in 0..width {
             add_edges(
-                &mut graph, &matrix, row, 
+                &mut graph, &matrix, rules, row,
                 column, width, height
             );
         }
@@ -276,20 +333,21 @@ fn parse_graph(file_name: &str) -> std::io::Result<(Graph, NodeIndex, NodeIndex,
 }
 
 fn add_edges(
-        graph: &mut Graph, matrix: &Vec<u32>, 
+        graph: &mut Graph, matrix: &[u32], rules: &ClimbRules,
         row: u32, column: u32, width: u32, height: u32
 ) {
-    let offsets: Vec<(i32, i32)> = Vec::from([(0, 1), (0, -1), (1, 0), (-1, 0)]);
+    let mut offsets: Vec<(i32, i32)> = Vec::from([(0, 1), (0, -1), (1, 0), (-1, 0)]);
+
+    if rules.diagonal {
+        offsets.extend([(1, 1), (1, -1), (-1, 1), (-1, -1)]);
+    }
+
     let node_index = get_index(width, row, column);
-    let node_val = matrix[node_index];
-    let node_ch = char::from_u32(node_val + 'a' as u32).unwrap();
+    let node_val = elevation(matrix[node_index]);
 
-    print!("  n[{}]({},{}) ->", node_ch, row, column);
-    
     for offset in offsets {
         let edge_row = offset.0 + row as i32;
         let edge_col = offset.1 + column as i32;
-        let edge_ch = char::from_u32(node_val + 'a' as u32).unwrap();
 
         if !is_valid_index(width, height, edge_row, edge_col) {
             continue; // Out of bounds 
@@ -298,22 +356,28 @@ fn add_edges(
         let edge_index = get_index(
             width, edge_row.try_into().unwrap(), edge_col.try_into().unwrap()
         );
-        let edge_val = match matrix[edge_index] {
-            27 => 25,
-            26 => 0,
-            v @ _ => v
-        };
+        let edge_val = elevation(matrix[edge_index]);
 
-        if edge_val > (node_val + 1) {
+        if edge_val.saturating_sub(node_val) > rules.max_climb {
             continue; // Edge position is too high up
         }
 
-        print!(" e[{}]({},{}),", edge_ch, edge_row, edge_col);
+        if node_val.saturating_sub(edge_val) > rules.max_descent {
+            continue; // Edge position is too far down
+        }
 
         graph.add_edge(node_index, edge_index);
     }
+}
 
-    println!();
+
+// Start is at elevation a and the end at elevation z
+fn elevation(val: u32) -> u32 {
+    match val {
+        START_VAL => 0,
+        END_VAL => 25,
+        v => v,
+    }
 }
 
 
@@ -345,22 +409,6 @@ fn parse_as_matrix(file_name: &str) -> std::io::Result<(Vec<u32>, u32, u32)> {
     }
 
 
-    println!("Matrix Form: ");
-    for row in 0..height {
-        print!("  ");
-        for column in 0..width {
-            let out = match matrix[get_index(width, row, column)] {
-                26 => 'S',
-                27 => 'E',
-                v @ _ => char::from_u32('a' as u32 + v).unwrap(), 
-            };
-
-            print!("{}", out);
-        }
-        println!();
-    }
-
-
     Ok((matrix, width, height))
 }
 
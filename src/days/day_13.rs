@@ -3,93 +3,107 @@ use std::io::BufReader;
 use std::io::prelude::*;
 
 use std::iter::Peekable;
+use std::str::Chars;
 
 use std::cmp::Ordering;
+use std::fmt;
 
-use crate::d13::PacketElement::*;
-use crate::d13::Comparison::*;
-
+use serde_json::Value;
 
+use crate::d13::PacketElement::*;
+use crate::days::arg_or;
 
-struct Packet {
-    elements: Vec<PacketElement>,    
-}
 
+// Packets are ordered by the distress signal rules, so a number is equal
+// to the list holding just that number
 #[derive(Clone)]
 enum PacketElement {
-    Num(u32),
+    Num(i64),
     Lst(Vec<PacketElement>),
 }
 
-enum Comparison {
-    Continue,
-    OutOfOrder,
-    InOrder
+#[derive(Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+}
+
+// One step towards the element that decided a comparison
+enum Step {
+    Index(usize),
+    Promote(Side),
+}
+
+enum Rule {
+    Numbers(i64, i64),
+    RanOut { left_len: usize, right_len: usize },
+}
+
+// Why two packets compare the way they do
+struct Explanation {
+    ordering: Ordering,
+    path: Vec<Step>,
+    rule: Rule,
 }
 
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
-    let packets = match parse_packets(file_name) {
-        Some(packets) => packets?,
-        _ => panic!(),
+    let packets = match parse_packets(file_name)? {
+        Some(packets) => packets,
+        None => panic!("Invalid packet"),
     };
 
+    // Usage: cargo run -- 13 [json | check]
+    match arg_or(2, String::new()).as_str() {
+        "json" => {
+            let json = Value::Array(packets.iter()
+                .flat_map(|(left, right)| [Value::from(left), Value::from(right)])
+                .collect());
+            println!("{}", serde_json::to_string_pretty(&json).unwrap());
+            return Ok(());
+        },
+        "check" => {
+            check_round_trips(&packets);
+            return Ok(());
+        },
+        _ => (),
+    }
+
     let mut score = 0;
-    let mut not_sure_count = 0;
     let mut list = Vec::new();
 
-    for (i, (left, right)) in packets.iter().enumerate() {
-        left.print();
-        right.print();
-
-        let left_as_element = Lst(left.elements.clone());
-        let right_as_element = Lst(right.elements.clone());
-        
-        match compare_elements(&left_as_element, &right_as_element, 0, true) {
-            InOrder => { 
-                println!("In Order: {}", i + 1);
-                score += i + 1;
-            },
-            OutOfOrder =>println!("Out of Order"),
-            Continue => {
-                println!("Not Sure");
-                not_sure_count += 1;
-            }
-        };
-        println!();
+    for (i, (left, right)) in packets.into_iter().enumerate() {
+        let explanation = explain(&left, &right);
 
-        list.push(left_as_element);
-        list.push(right_as_element);
+        println!("{}\n{}\n{}\n", left, right, explanation);
+
+        if explanation.ordering == Ordering::Less {
+            score += i + 1;
+        }
+
+        list.push(left);
+        list.push(right);
     }
 
-    println!("score: {}, not sure count: {}", score, not_sure_count);
+    println!("score: {}", score);
 
     // Add decoder packets
-    list.push(Lst(vec![Lst(vec![Num(2)])]));
-    list.push(Lst(vec![Lst(vec![Num(6)])]));
+    let dividers = [
+        Lst(vec![Lst(vec![Num(2)])]),
+        Lst(vec![Lst(vec![Num(6)])]),
+    ];
 
-    list.sort_by(|a, b| match compare_elements(&a, &b, 0, false) {
-        InOrder => Ordering::Less,
-        OutOfOrder => Ordering::Greater,
-        Continue => Ordering::Equal,
-    });
+    list.extend(dividers.iter().cloned());
+    list.sort();
 
     let mut part_2_score = 1;
 
     for (i, packet) in list.iter().enumerate() {
-        packet.print();
-        println!();
-
-        part_2_score *= match packet {
-            Lst(inner) => match &inner[..] {
-                [Lst(second_inner)] => match &second_inner[..] {
-                    [Num(2)] | [Num(6)] => (i + 1),
-                    _ => 1,
-                },
-                _ => 1,
-            },
-            _ => 1,
-        };
+        println!("{}", packet);
+
+        if dividers.iter().any(|d| d.to_string() == packet.to_string()) {
+            part_2_score *= i + 1;
+        }
     }
 
     println!("part 2 score: {}", part_2_score);
@@ -97,204 +111,321 @@ pub fn run(file_name: &str) -> std::io::Result<()> {
     Ok(())
 }
 
+// Every packet must print back to text and JSON that parses to itself
+fn check_round_trips(packets: &[(PacketElement, PacketElement)]) {
+    let mut failures = 0;
+
+    for packet in packets.iter().flat_map(|(left, right)| [left, right]) {
+        let text = packet.to_string();
+        let from_text = parse_packet(&text).map(|p| p.to_string());
+        let from_json = PacketElement::try_from(&Value::from(packet)).map(|p| p.to_string());
+
+        if from_text.as_ref() != Some(&text) || from_json.as_ref() != Ok(&text) {
+            println!("round trip failed: {}", text);
+            failures += 1;
+        }
+    }
+
+    println!("{} packets checked, {} failures", packets.len() * 2, failures);
+}
+
 /* Functionality */
 
-fn compare_elements(
-        left: &PacketElement, right: &PacketElement, 
-        depth: u32, debug: bool
-) -> Comparison {
-    if debug {
-        print!("{: <1$} - compare ", "", depth as usize);
-        left.print();
-        print!(" vs ");
-        right.print();
-        println!();
+// A number compared with a list is compared as a list of just that
+// number, borrowed in place so sorting doesn't allocate
+fn compare_elements(left: &PacketElement, right: &PacketElement) -> Ordering {
+    match (left, right) {
+        (Num(lv), Num(rv)) => lv.cmp(rv),
+        (Lst(ll), Lst(rl)) => compare_lists(ll, rl),
+        (Num(_), Lst(rl)) => compare_lists(std::slice::from_ref(left), rl),
+        (Lst(ll), Num(_)) => compare_lists(ll, std::slice::from_ref(right)),
     }
+}
+
+fn compare_lists(left: &[PacketElement], right: &[PacketElement]) -> Ordering {
+    left.iter().zip(right.iter())
+        .map(|(left_child, right_child)| compare_elements(left_child, right_child))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| left.len().cmp(&right.len()))
+}
 
+fn explain(left: &PacketElement, right: &PacketElement) -> Explanation {
+    let mut explanation = explain_elements(left, right);
+    explanation.path.reverse();
+    explanation
+}
+
+// Steps are pushed on the way back out, so the path comes out deepest
+// first until explain reverses it
+fn explain_elements(left: &PacketElement, right: &PacketElement) -> Explanation {
     match (left, right) {
-        (Num(lv), Num(rv)) => 
-            compare_numbers(*lv, *rv),
-        (Lst(ll), Lst(rl)) => 
-            compare_lists(ll, rl, depth, debug),
-        (Num(lv), _) => 
-            compare_elements(
-                &to_list(*lv, depth + 1, debug), right, depth + 1, debug
-            ),
-        (_, Num(rv)) => 
-            compare_elements(
-                left, &to_list(*rv, depth + 1, debug), depth + 1, debug
-            ),
+        (Num(lv), Num(rv)) => Explanation {
+            ordering: lv.cmp(rv),
+            path: Vec::new(),
+            rule: Rule::Numbers(*lv, *rv),
+        },
+        (Lst(ll), Lst(rl)) =>
+            explain_lists(ll, rl),
+        (Num(lv), _) =>
+            explain_elements(&to_list(*lv), right).after(Step::Promote(Side::Left)),
+        (_, Num(rv)) =>
+            explain_elements(left, &to_list(*rv)).after(Step::Promote(Side::Right)),
     }
 }
 
-fn to_list(val: u32, depth: u32, debug: bool) -> PacketElement {
-    let mut list = Vec::new();
+fn to_list(val: i64) -> PacketElement {
+    Lst(vec![Num(val)])
+}
+
+fn explain_lists(left: &[PacketElement], right: &[PacketElement]) -> Explanation {
+    for (i, (left_child, right_child)) in left.iter().zip(right.iter()).enumerate() {
+        let explanation = explain_elements(left_child, right_child);
 
-    if debug {
-        println!("{: <1$} - Mixed types; convert val to list", "", depth as usize);
+        if explanation.ordering != Ordering::Equal {
+            return explanation.after(Step::Index(i));
+        }
     }
 
-    list.push(Num(val));
+    Explanation {
+        ordering: left.len().cmp(&right.len()),
+        path: Vec::new(),
+        rule: Rule::RanOut { left_len: left.len(), right_len: right.len() },
+    }
+}
 
-    Lst(list)
+impl Explanation {
+    // Records the step taken to get here
+    fn after(mut self, step: Step) -> Explanation {
+        self.path.push(step);
+        self
+    }
 }
 
-fn compare_numbers(left: u32, right: u32) -> Comparison{
-    if left < right {
-        InOrder
-    } else if left > right  {
-        OutOfOrder
-    } else {
-        Continue
+
+impl Ord for PacketElement {
+    fn cmp(&self, other: &PacketElement) -> Ordering {
+        compare_elements(self, other)
     }
 }
 
-fn compare_lists(
-        left: &Vec<PacketElement>, right: &Vec<PacketElement>, 
-        depth: u32, debug: bool
-) -> Comparison {
-    for (left_child, right_child) in left.iter().zip(right.iter()) {
-        match compare_elements(left_child, right_child, depth + 1, debug) {
-            Continue => (),
-            stop @ _ => return stop,
-        };
+impl PartialOrd for PacketElement {
+    fn partial_cmp(&self, other: &PacketElement) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
+}
 
-    if left.len() < right.len() {
-        InOrder 
-    } else if left.len() > right.len() {
-        OutOfOrder
-    } else {
-        Continue
+impl PartialEq for PacketElement {
+    fn eq(&self, other: &PacketElement) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
 }
 
+impl Eq for PacketElement {}
 
-/* Debug */
 
-impl Packet {
-    fn print(&self) {
-       let element = Lst(self.elements.clone());
-       element.print();
-       println!();
-    }
-}
+/* Printing */
 
-impl PacketElement {
-    fn print(&self) {
+impl fmt::Display for PacketElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Num(v) => print!("{}", v),
+            Num(v) => write!(f, "{}", v),
             Lst(l) => {
-                print!("[");
-                for element in l {
-                    element.print();
-                    print!(",");
+                write!(f, "[")?;
+                for (i, element) in l.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", element)?;
                 }
-                print!("]")
+                write!(f, "]")
             }
         }
     }
 }
 
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.ordering {
+            Ordering::Less => write!(f, "in order")?,
+            Ordering::Greater => write!(f, "out of order")?,
+            Ordering::Equal => write!(f, "equal")?,
+        };
+
+        write!(f, " at packet")?;
+
+        for step in &self.path {
+            match step {
+                Step::Index(i) => write!(f, "[{}]", i)?,
+                Step::Promote(Side::Left) => write!(f, "(left to list)")?,
+                Step::Promote(Side::Right) => write!(f, "(right to list)")?,
+            };
+        }
+
+        match self.rule {
+            Rule::Numbers(l, r) =>
+                write!(f, ": compared numbers {} vs {}", l, r),
+            Rule::RanOut { left_len, right_len } if left_len < right_len =>
+                write!(f, ": left list ran out after {} items", left_len),
+            Rule::RanOut { left_len, right_len } if left_len > right_len =>
+                write!(f, ": right list ran out after {} items", right_len),
+            Rule::RanOut { left_len, .. } =>
+                write!(f, ": both lists ran out after {} items", left_len),
+        }
+    }
+}
+
+
+/* JSON */
+
+impl From<&PacketElement> for Value {
+    fn from(element: &PacketElement) -> Value {
+        match element {
+            Num(v) => Value::from(*v),
+            Lst(l) => Value::Array(l.iter().map(Value::from).collect()),
+        }
+    }
+}
+
+impl TryFrom<&Value> for PacketElement {
+    type Error = String;
+
+    fn try_from(value: &Value) -> Result<PacketElement, String> {
+        match value {
+            Value::Number(n) => n.as_i64()
+                .map(Num)
+                .ok_or(format!("{} is not an integer", n)),
+            Value::Array(l) => l.iter()
+                .map(PacketElement::try_from)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Lst),
+            other => Err(format!("{} is not a number or array", other)),
+        }
+    }
+}
+
+
 /* Parsing */
 
-fn parse_packets(file_name: &str) -> Option<std::io::Result<Vec<(Packet, Packet)>>> {
+fn parse_packets(
+    file_name: &str
+) -> std::io::Result<Option<Vec<(PacketElement, PacketElement)>>> {
     let file = fs::File::open(file_name)
         .expect("File not found");
     let reader = BufReader::new(file);
-    let mut lines = reader.lines();
 
-    let mut output = Vec::new();
+    let mut packets = Vec::new();
 
-    loop {
-        let l1 = lines.next()?.ok()?;
-        let l2 = lines.next()?.ok()?;
-        
-        let p1 = parse_packet(&l1)?;
-        let p2 = parse_packet(&l2)?;
+    for l in reader.lines() {
+        let line = l?;
 
-        output.push((p1, p2));
+        if line.trim().is_empty() {
+            continue;
+        }
 
-        if let None = lines.next() {
-            break;
+        match parse_packet(&line) {
+            Some(packet) => packets.push(packet),
+            None => return Ok(None),
         }
     }
 
-    Some(Ok(output))
-}
-
+    if packets.len() % 2 != 0 {
+        return Ok(None);
+    }
 
-fn parse_packet(line: &str) -> Option<Packet> {
-    let chars = line.chars().collect::<Vec<char>>();
-    let mut chars_iter = chars.iter().peekable();
-    let body = parse_packet_lst(&mut chars_iter)?; 
+    let mut output = Vec::new();
+    let mut packets = packets.into_iter();
 
-    if let Lst(elements) = body {
-        Some(Packet {
-            elements: elements, 
-        })
-    } else {
-        None
+    while let (Some(p1), Some(p2)) = (packets.next(), packets.next()) {
+        output.push((p1, p2));
     }
+
+    Ok(Some(output))
 }
 
 
-fn parse_packet_element<'a, I>(chars: &mut Peekable<I>) -> Option<PacketElement> 
-where I: Iterator<Item = &'a char> {
-    parse_packet_lst(chars)
-        .or(parse_packet_num(chars))   
+// A packet is a list, whitespace is allowed between any two tokens
+fn parse_packet(line: &str) -> Option<PacketElement> {
+    let mut chars = line.chars().peekable();
+    let body = parse_packet_lst(&mut chars)?;
+
+    skip_whitespace(&mut chars);
+
+    match chars.next() {
+        None => Some(body),
+        Some(_) => None,
+    }
 }
 
 
-fn parse_packet_num<'a, I>(chars: &mut Peekable<I>) -> Option<PacketElement> 
-where I: Iterator<Item = &'a char> {
-    let mut output = 0;
+fn parse_packet_element(chars: &mut Peekable<Chars>) -> Option<PacketElement> {
+    skip_whitespace(chars);
 
     match chars.peek()? {
-        '0'..='9' => (),
-        _ => return None,
+        '[' => parse_packet_lst(chars),
+        _ => parse_packet_num(chars),
+    }
+}
+
+
+fn parse_packet_num(chars: &mut Peekable<Chars>) -> Option<PacketElement> {
+    let sign = match chars.peek()? {
+        '-' => {
+            chars.next();
+            -1
+        },
+        _ => 1,
     };
 
-    loop {
-        match chars.peek()? {
-            ch @ '0'..='9' => {
-                output = output * 10 + (**ch as u32) - ('0' as u32);
-                chars.next();
-            }
-            ',' => break,
-            ']' => break,
-            _ => return None,
-        };
+    let mut output: i64 = 0;
+    let mut digits = 0;
+
+    while let Some(ch @ '0'..='9') = chars.peek().copied() {
+        output = output.checked_mul(10)?.checked_add((ch as i64) - ('0' as i64))?;
+        digits += 1;
+        chars.next();
+    }
+
+    if digits == 0 {
+        return None;
     }
 
-    Some(Num(output))
+    Some(Num(sign * output))
 }
 
-fn parse_packet_lst<'a, I>(chars: &mut Peekable<I>) -> Option<PacketElement> 
-where I: Iterator<Item = &'a char> {
-    if **chars.peek()? != '[' {
+fn parse_packet_lst(chars: &mut Peekable<Chars>) -> Option<PacketElement> {
+    skip_whitespace(chars);
+
+    if chars.next()? != '[' {
         return None;
     }
 
-    chars.next();
-
     let mut output = Vec::new();
 
-    while let Some(element) = parse_packet_element(chars) {
-        output.push(element);
-        if let Some(ch) = chars.peek() {
-            if **ch == ',' {
-                chars.next();
-            }
-        }
-    }    
+    skip_whitespace(chars);
 
-    if *chars.next()? != ']' {
-        return None;
+    if chars.peek()? == &']' {
+        chars.next();
+        return Some(Lst(output));
     }
 
-    Some(Lst(output))
-}
-
+    loop {
+        output.push(parse_packet_element(chars)?);
+        skip_whitespace(chars);
 
+        match chars.next()? {
+            ',' => (),
+            ']' => return Some(Lst(output)),
+            _ => return None,
+        }
+    }
+}
 
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while let Some(ch) = chars.peek() {
+        if !ch.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
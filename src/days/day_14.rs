@@ -3,22 +3,34 @@ use std::io::BufReader;
 use std::io::prelude::*;
 
 use std::iter::Peekable;
+use std::str::CharIndices;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
 
+use std::fmt;
+
 use std::cmp::Ordering::*;
 
-use crate::d14::CaveEntity::*;
+use bit_vec::BitVec;
+
 use crate::d14::Heading::*;
 
+// Ends of a straight line of rock
+type Run = ((u32, u32), (u32, u32));
+
+// Dense grid covering every cell sand can reach. Rock and sand share the
+// occupied bitset so a drop only has to check one bit per step
 struct CaveSystem {
-    entities: HashMap<(u32, u32), CaveEntity>,
-    sand_path: Option<HashSet<(u32, u32)>>,
-    sand_source: (u32, u32),
-    top_left: (u32, u32),
+    occupied: BitVec,
+    rock: BitVec,
+    sand_sources: Vec<(u32, u32)>,
+    // Path the last grain from each source took, starting at the source
+    sand_paths: Vec<Vec<(u32, u32)>>,
+    left: u32,
     width: u32,
     height: u32,
+    // Floor sits on the row just below the grid, otherwise that's the void
     has_floor: bool,
 }
 
@@ -28,9 +40,10 @@ enum SandDrop {
     Full,
 }
 
-enum CaveEntity {
-    Rock,
-    Sand,
+enum Fall {
+    To((u32, u32)),
+    Rest,
+    Void,
 }
 
 enum Heading {
@@ -50,17 +63,65 @@ struct RockDefinition {
     path: VecDeque<Direction>,
 }
 
+struct ParseError {
+    column: usize,
+    reason: String,
+}
+
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
-    let mut cave_system = parse_cave_system(file_name, true)?;
+    let mut floor_depth = Some(2);
+    let mut sources = Vec::new();
+    let mut flood = false;
+    let mut print = false;
+    let mut paths = false;
+
+    // Usage: cargo run -- 14 [void | floor DEPTH] [source X,Y]... [flood] [print]
+    //                        [paths]
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut next = || args.next().expect("Missing argument");
+
+        match arg.as_str() {
+            "void" => floor_depth = None,
+            "floor" => floor_depth = Some(next().parse().unwrap()),
+            "source" => sources.push(parse_source(next())
+                .expect("Source must be X,Y")),
+            "flood" => flood = true,
+            "print" => print = true,
+            "paths" => paths = true,
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
+    if sources.is_empty() {
+        sources.push((500, 0));
+    }
+
+    let rocks = parse_rocks(file_name)?;
+    let mut cave_system = CaveSystem::new(&rocks, sources, floor_depth);
+
+    if paths {
+        let rock_paths = cave_system.rock_paths();
+
+        for rock in &rock_paths {
+            println!("{}", rock);
+        }
+        println!("{} rock paths, {} in input", rock_paths.len(), rocks.len());
+        return Ok(());
+    }
 
     println!("width: {}, height: {}", cave_system.width, cave_system.height);
-    cave_system.print();
 
-    let mut count = 0;
+    let count = if flood && cave_system.has_floor {
+        cave_system.flood_fill()
+    } else {
+        cave_system.fill()
+    };
 
-    while let SandDrop::Ok(_) = cave_system.spawn_sand() {
-        count += 1;
+    if print {
         cave_system.print();
     }
 
@@ -71,102 +132,295 @@ pub fn run(file_name: &str) -> std::io::Result<()> {
 
 
 impl CaveSystem {
-    fn spawn_sand(&mut self) -> SandDrop {
-        let result = self.drop_sand(&self.sand_source);
-        
-        if let SandDrop::Ok(pos) = &result {
-            println!("{}, {}", pos.0, pos.1);
-            self.add_entitie(*pos, Sand);
+    // Sizes the grid from the rock bounds. With a floor the sand piles up
+    // in a triangle under each source, so the grid is widened to fit it
+    fn new(
+        rocks: &[RockDefinition], sand_sources: Vec<(u32, u32)>, floor_depth: Option<u32>
+    ) -> CaveSystem {
+        let cells: Vec<(u32, u32)> = rocks.iter().flat_map(|r| r.cells()).collect();
+
+        let lowest = cells.iter()
+            .chain(sand_sources.iter())
+            .map(|p| p.1)
+            .max()
+            .unwrap_or(0);
+
+        let height = match floor_depth {
+            Some(depth) => lowest + depth.max(1),
+            None => lowest + 1,
+        };
+
+        let mut min_x = cells.iter().chain(sand_sources.iter()).map(|p| p.0).min().unwrap_or(0);
+        let mut max_x = cells.iter().chain(sand_sources.iter()).map(|p| p.0).max().unwrap_or(0);
+
+        if floor_depth.is_some() {
+            for source in &sand_sources {
+                let spread = height.saturating_sub(source.1);
+                min_x = min_x.min(source.0.saturating_sub(spread));
+                max_x = max_x.max(source.0 + spread);
+            }
         }
 
-        result
+        // One spare column each side for grains spilling off the rocks
+        let left = min_x.saturating_sub(1);
+        let width = max_x + 2 - left;
+        let size = (width * height) as usize;
+
+        let mut cave_system = CaveSystem {
+            occupied: BitVec::from_elem(size, false),
+            rock: BitVec::from_elem(size, false),
+            sand_paths: vec![Vec::new(); sand_sources.len()],
+            sand_sources,
+            left,
+            width,
+            height,
+            has_floor: floor_depth.is_some(),
+        };
+
+        for pos in cells {
+            let i = cave_system.index(pos).unwrap();
+            cave_system.occupied.set(i, true);
+            cave_system.rock.set(i, true);
+        }
+
+        cave_system
     }
 
-    fn drop_sand(&self, pos: &(u32, u32)) -> SandDrop {
-        let mut current_pos = *pos;
+    fn index(&self, pos: (u32, u32)) -> Option<usize> {
+        if pos.0 < self.left || pos.0 >= self.left + self.width || pos.1 >= self.height {
+            return None;
+        }
+
+        Some((pos.1 * self.width + pos.0 - self.left) as usize)
+    }
+
+    fn is_sand(&self, pos: (u32, u32)) -> bool {
+        match self.index(pos) {
+            Some(i) => self.occupied[i] && !self.rock[i],
+            None => false,
+        }
+    }
+
+    // Drops grains from every source in turn until each one is either
+    // blocked or losing sand to the void, returns the grains that settled
+    fn fill(&mut self) -> usize {
+        let mut active: Vec<usize> = (0..self.sand_sources.len()).collect();
+        let mut count = 0;
+
+        while !active.is_empty() {
+            active.retain(|source| match self.spawn_sand(*source) {
+                SandDrop::Ok(_) => {
+                    count += 1;
+                    true
+                },
+                SandDrop::Void | SandDrop::Full => false,
+            });
+        }
+
+        count
+    }
+
+    // The next grain follows the previous one until the cell it came to
+    // rest in, so it can start from the step before that
+    fn spawn_sand(&mut self, source: usize) -> SandDrop {
+        let mut path = std::mem::take(&mut self.sand_paths[source]);
+
+        if path.is_empty() {
+            let start = self.sand_sources[source];
 
-        loop {
-            // Check if in void
-            if current_pos.1 > self.height {
-                return SandDrop::Void;
+            match self.index(start) {
+                Some(i) if self.occupied[i] => return SandDrop::Full,
+                _ => path.push(start),
             }
+        }
 
-            // Try going down
-            let down = (current_pos.0, current_pos.1 + 1);
+        let result = loop {
+            let pos = *path.last().unwrap();
 
-            if self.space_empty(&down) {
-                current_pos = down;
-                continue;
+            match self.fall_from(pos) {
+                Fall::To(next) => path.push(next),
+                Fall::Void => break SandDrop::Void,
+                Fall::Rest => {
+                    path.pop();
+                    break SandDrop::Ok(pos);
+                },
             }
+        };
 
-            // Try going down left
-            let down_left = (current_pos.0 - 1, current_pos.1 + 1);
+        self.sand_paths[source] = path;
 
-            if self.space_empty(&down_left) {
-                current_pos = down_left;
-                continue;
+        if let SandDrop::Ok(pos) = result {
+            self.occupied.set(self.index(pos).unwrap(), true);
+            self.invalidate_paths(pos);
+        }
+
+        result
+    }
+
+    fn fall_from(&self, pos: (u32, u32)) -> Fall {
+        let below = pos.1 + 1;
+
+        if below == self.height {
+            return if self.has_floor { Fall::Rest } else { Fall::Void };
+        }
+
+        // Left of column 0 wraps around to a column outside the grid
+        for x in [pos.0, pos.0.wrapping_sub(1), pos.0 + 1] {
+            match self.index((x, below)) {
+                // Nothing outside the grid to land on
+                None => return Fall::Void,
+                Some(i) if !self.occupied[i] => return Fall::To((x, below)),
+                Some(_) => (),
             }
+        }
 
-            // Try going down right 
-            let down_right = (current_pos.0 + 1, current_pos.1 + 1);
+        Fall::Rest
+    }
 
-            if self.space_empty(&down_right) {
-                current_pos = down_right;
+    // A grain landing below a remembered path changes where that path
+    // goes next, so it's cut back to the cell above the grain. Paths
+    // move down one row per step, so that cell is found by its row. A
+    // grain burying a source drops its whole path, so its next spawn
+    // sees the source is full
+    fn invalidate_paths(&mut self, pos: (u32, u32)) {
+        for (source, path) in self.sand_sources.iter().zip(self.sand_paths.iter_mut()) {
+            if pos == *source {
+                path.clear();
                 continue;
             }
 
-            // Can't go any furhter 
-            if current_pos == self.sand_source {
-                return SandDrop::Full;
+            if pos.1 <= source.1 {
+                continue;
             }
 
-            return SandDrop::Ok(current_pos);
+            let parent = (pos.1 - 1 - source.1) as usize;
+
+            if parent + 1 < path.len() && path[parent].0.abs_diff(pos.0) <= 1 {
+                path.truncate(parent + 1);
+            }
         }
     }
 
-    fn space_empty(&self, pos: &(u32, u32)) -> bool {
-        if self.has_floor && pos.1 == self.height {
-            return false;
+    // With a floor every grain settles, so the sand ends up filling
+    // exactly the cells reachable from a source. Those are found a row
+    // at a time from the cells above instead of dropping each grain
+    fn flood_fill(&mut self) -> usize {
+        let mut count = 0;
+
+        for y in 0..self.height {
+            for x in self.left..self.left + self.width {
+                let i = self.index((x, y)).unwrap();
+
+                if self.occupied[i] {
+                    continue;
+                }
+
+                let reached = self.sand_sources.contains(&(x, y)) || (y > 0 &&
+                    [x.wrapping_sub(1), x, x + 1].iter().any(|px| self.is_sand((*px, y - 1))));
+
+                if reached {
+                    self.occupied.set(i, true);
+                    count += 1;
+                }
+            }
         }
 
-        !self.entities.contains_key(pos) 
+        count
     }
 
-    fn add_rock(&mut self, rock: RockDefinition) {
-        self.sand_path = None;
-        self.add_entitie(rock.start_pos, Rock);
+    // Rock as a small set of paths that rebuild the same cave. Each rock
+    // cell is covered by the longer of its two straight runs, and runs
+    // meeting end to end are chained into a single path
+    fn rock_paths(&self) -> Vec<RockDefinition> {
+        let runs = self.rock_runs();
+        let mut ends: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
 
-        let mut pos = rock.start_pos;
+        for (i, (start, end)) in runs.iter().enumerate() {
+            ends.entry(*start).or_default().push(i);
+            ends.entry(*end).or_default().push(i);
+        }
 
-        // Follow path
-        for direction in &rock.path {
-            for _ in 0..direction.distance {
-                pos = advance_pos_in_direction(pos, &direction.heading);
-                self.add_entitie(pos, Rock);
+        let mut used = vec![false; runs.len()];
+        let mut paths = Vec::new();
+
+        for i in 0..runs.len() {
+            if used[i] {
+                continue;
+            }
+            used[i] = true;
+
+            let (start, end) = runs[i];
+            let mut points = VecDeque::from([start]);
+
+            // A lone rock is a path of one point
+            if start != end {
+                points.push_back(end);
+
+                while let Some(next) = take_run(&runs, &ends, &mut used, *points.back().unwrap()) {
+                    points.push_back(next);
+                }
+                while let Some(next) = take_run(&runs, &ends, &mut used, *points.front().unwrap()) {
+                    points.push_front(next);
+                }
             }
+
+            paths.push(RockDefinition::from_points(points.make_contiguous()));
         }
+
+        paths
     }
 
-    fn add_entitie(&mut self, pos: (u32, u32), entitie: CaveEntity) {
-        self.entities.insert(pos, entitie);
+    // Every straight run of rock that is the longer one for some cell
+    fn rock_runs(&self) -> Vec<Run> {
+        let mut runs = Vec::new();
+        let mut seen = HashSet::new();
 
-        // Update witdh
-        if pos.0 < self.top_left.0 {
-            self.width += self.top_left.0 - pos.0;
-            self.top_left.0 = pos.0;
-        } else if pos.0 >= self.top_left.0 + self.width {
-            self.width = (pos.0 - self.top_left.0) + 1;
+        for y in 0..self.height {
+            for x in self.left..self.left + self.width {
+                if !self.is_rock((x, y)) {
+                    continue;
+                }
+
+                let across = self.run_through((x, y), (1, 0));
+                let down = self.run_through((x, y), (0, 1));
+
+                let run = if run_length(across) >= run_length(down) { across } else { down };
+
+                if seen.insert(run) {
+                    runs.push(run);
+                }
+            }
         }
 
-        // Update height
-        if pos.1 >= self.height {
-            self.height = pos.1 + 1;
+        runs
+    }
+
+    // Ends of the straight line of rock through a cell
+    fn run_through(&self, pos: (u32, u32), step: (u32, u32)) -> Run {
+        let mut start = pos;
+        let mut end = pos;
+
+        while start.0 >= step.0 && start.1 >= step.1 &&
+              self.is_rock((start.0 - step.0, start.1 - step.1)) {
+            start = (start.0 - step.0, start.1 - step.1);
+        }
+
+        while self.is_rock((end.0 + step.0, end.1 + step.1)) {
+            end = (end.0 + step.0, end.1 + step.1);
+        }
+
+        (start, end)
+    }
+
+    fn is_rock(&self, pos: (u32, u32)) -> bool {
+        match self.index(pos) {
+            Some(i) => self.rock[i],
+            None => false,
         }
     }
 
     fn print(&self) {
-        // Todo: print header
-        let chars_for_left = length(self.height - 1, 10);
+        let chars_for_left = length(self.height, 10);
 
         for row in 0..self.height {
             self.print_row(row, chars_for_left);
@@ -189,22 +443,19 @@ impl CaveSystem {
         print!("{} ", row);
 
         // Print elements
-        let row_i = self.top_left.1 + row;
-
         for col in 0..self.width {
-            let col_i = self.top_left.0 + col;
-            let pos = (col_i, row_i);
-
-            if pos == self.sand_source {
+            let pos = (self.left + col, row);
+            let i = self.index(pos).unwrap();
+
+            if self.rock[i] {
+                print!("#");
+            } else if self.occupied[i] {
+                print!("o");
+            } else if self.sand_sources.contains(&pos) {
                 print!("+");
-                continue;
+            } else {
+                print!(".");
             }
-
-            match self.entities.get(&pos) {
-                Some(Rock) => print!("#"),
-                Some(Sand) => print!("o"),
-                None => print!("."),
-            };
         }
 
         println!();
@@ -212,20 +463,28 @@ impl CaveSystem {
 }
 
 impl RockDefinition {
-    fn print(&self) {
-        print!("s({}, {}): ", self.start_pos.0, self.start_pos.1);
+    fn from_points(points: &[(u32, u32)]) -> RockDefinition {
+        RockDefinition {
+            start_pos: points[0],
+            path: points.windows(2)
+                .map(|w| segment(w[0], w[1]).expect("Rock paths must be straight lines"))
+                .collect(),
+        }
+    }
+
+    // Every cell the rock path covers
+    fn cells(&self) -> Vec<(u32, u32)> {
+        let mut pos = self.start_pos;
+        let mut cells = vec![pos];
 
         for direction in &self.path {
-            match direction.heading {
-                Up => print!("U"),
-                Down => print!("D"),
-                Left => print!("L"),
-                Right => print!("R"),
-            };
-            print!(" {} -> ", direction.distance);
+            for _ in 0..direction.distance {
+                pos = advance_pos_in_direction(pos, &direction.heading);
+                cells.push(pos);
+            }
         }
 
-        println!();
+        cells
     }
 }
 
@@ -239,110 +498,187 @@ fn advance_pos_in_direction(pos: (u32, u32), heading: &Heading) -> (u32, u32) {
 }
 
 
+// Marks an unused run ending at pos as used and returns its other end
+fn take_run(
+    runs: &[Run],
+    ends: &HashMap<(u32, u32), Vec<usize>>,
+    used: &mut [bool],
+    pos: (u32, u32)
+) -> Option<(u32, u32)> {
+    let i = *ends.get(&pos)?.iter().find(|i| !used[**i])?;
+    used[i] = true;
+
+    let (start, end) = runs[i];
+    Some(if start == pos { end } else { start })
+}
+
+fn run_length(run: Run) -> u32 {
+    (run.1.0 - run.0.0) + (run.1.1 - run.0.1)
+}
+
+
+/* Printing */
+
+impl fmt::Display for RockDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pos = self.start_pos;
+        write!(f, "{},{}", pos.0, pos.1)?;
+
+        for direction in &self.path {
+            for _ in 0..direction.distance {
+                pos = advance_pos_in_direction(pos, &direction.heading);
+            }
+            write!(f, " -> {},{}", pos.0, pos.1)?;
+        }
+
+        Ok(())
+    }
+}
+
 
 /* Parsing */
 
-fn parse_cave_system(file_name: &str, has_floor: bool) -> std::io::Result<CaveSystem> {
+fn parse_rocks(file_name: &str) -> std::io::Result<Vec<RockDefinition>> {
     let file = fs::File::open(file_name)
         .expect("File not found");
     let reader = BufReader::new(file);
 
-    let mut cave_system = CaveSystem {
-        entities: HashMap::new(),
-        sand_path: None,
-        sand_source: (500, 0),
-        top_left: (500, 0),
-        width: 1,
-        height: 1,
-        has_floor: has_floor,
-    };
+    let mut rocks = Vec::new();
 
-    for l in reader.lines() {
-        let rock = parse_rock(&l?);
-        rock.print();
-        cave_system.add_rock(rock);
-    }
+    for (i, l) in reader.lines().enumerate() {
+        let line = l?;
+
+        if line.trim().is_empty() {
+            continue;
+        }
 
-    if has_floor {
-        cave_system.width += 2;
-        cave_system.height += 1;
-        cave_system.top_left.0 -= 1;
+        match parse_rock(&line) {
+            Ok(rock) => rocks.push(rock),
+            Err(error) => return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("line {}, column {}: {}", i + 1, error.column, error.reason)
+            )),
+        }
     }
 
-    Ok(cave_system)
+    Ok(rocks)
 }
 
-fn parse_rock(line: &str) -> RockDefinition {
-    let chars = line.chars().collect::<Vec<char>>();
-    let mut chars_iter = chars.iter().peekable();
-    let mut points = Vec::new();
+fn parse_source(arg: &str) -> Option<(u32, u32)> {
+    let (x, y) = arg.split_once(',')?;
+    Some((x.trim().parse().ok()?, y.trim().parse().ok()?))
+}
 
-    while let Some(pair) = parse_pair(&mut chars_iter) {
-        points.push(pair);
-    }
+// A rock is "x,y -> x,y -> ..." where every step is a horizontal or
+// vertical line, whitespace is allowed between any two tokens
+fn parse_rock(line: &str) -> Result<RockDefinition, ParseError> {
+    let mut chars = line.char_indices().peekable();
+    let start_pos = parse_pair(&mut chars, line)?;
 
+    let mut pos = start_pos;
     let mut path = VecDeque::new();
 
-    for i in 0..(points.len() - 1) {
-        let start = points[i];
-        let next = points[i + 1];
-        
-        // Determine direction 
-        let ordering = (
-            start.0.cmp(&next.0),
-            start.1.cmp(&next.1),
-        );
+    loop {
+        skip_whitespace(&mut chars);
 
-        let (heading, distance) = match ordering {
-            (Equal, Less) => (Up, next.1 - start.1),
-            (Equal, Greater) => (Down, start.1 - next.1),
-            (Less, Equal) => (Right, next.0 - start.0),
-            (Greater, Equal) => (Left, start.0 - next.0),
-            _ => panic!(),
-        };
+        let column = column_at(&mut chars, line);
 
-        path.push_back( Direction {
-            heading: heading,
-            distance: distance,
-        });
-    }
+        match (chars.next(), chars.next()) {
+            (None, _) => break,
+            (Some((_, '-')), Some((_, '>'))) => (),
+            _ => return Err(ParseError::new(column, "expected '->'")),
+        }
 
+        skip_whitespace(&mut chars);
 
-    RockDefinition {
-        start_pos: points[0],
-        path: path,
+        let column = column_at(&mut chars, line);
+        let next = parse_pair(&mut chars, line)?;
+
+        if next == pos {
+            return Err(ParseError::new(column, &format!(
+                "zero length line at {},{}", next.0, next.1
+            )));
+        }
+
+        path.push_back(segment(pos, next)
+            .ok_or_else(|| ParseError::new(column, &format!(
+                "diagonal line {},{} -> {},{}", pos.0, pos.1, next.0, next.1
+            )))?);
+        pos = next;
     }
+
+    Ok(RockDefinition { start_pos, path })
 }
 
-fn parse_pair<'a, I>(chars: &mut Peekable<I>) -> Option<(u32, u32)> 
-where I: Iterator<Item = &'a char> {
-    let l = parse_num(chars)?;
-    chars.next()?; // Skip ','
-    let r = parse_num(chars)?;
-    chars.next(); chars.next(); // Skip ' -> ' if present
-    chars.next(); chars.next();
-    Some((l, r))
+// Straight line between two points, None if it's diagonal or zero length
+fn segment(start: (u32, u32), next: (u32, u32)) -> Option<Direction> {
+    let ordering = (
+        start.0.cmp(&next.0),
+        start.1.cmp(&next.1),
+    );
+
+    let (heading, distance) = match ordering {
+        (Equal, Less) => (Up, next.1 - start.1),
+        (Equal, Greater) => (Down, start.1 - next.1),
+        (Less, Equal) => (Right, next.0 - start.0),
+        (Greater, Equal) => (Left, start.0 - next.0),
+        _ => return None,
+    };
+
+    Some(Direction { heading, distance })
 }
 
-fn parse_num<'a, I>(chars: &mut Peekable<I>) -> Option<u32> 
-where I: Iterator<Item = &'a char> {
-    let mut output = None;
-    let mut output_val = 0;
+fn parse_pair(chars: &mut Peekable<CharIndices>, line: &str) -> Result<(u32, u32), ParseError> {
+    skip_whitespace(chars);
+    let l = parse_num(chars, line)?;
+    skip_whitespace(chars);
 
-    loop {
-        match chars.peek() {
-            ch @ Some('0'..='9') => {
-                output_val = output_val * 10 + (
-                    **ch.unwrap() as u32 - '0' as u32
-                );
-                output = Some(output_val);
-                chars.next();
-            },
-            _ => break,
-        };
+    let column = column_at(chars, line);
+
+    if !matches!(chars.next(), Some((_, ','))) {
+        return Err(ParseError::new(column, "expected ','"));
+    }
+
+    skip_whitespace(chars);
+    let r = parse_num(chars, line)?;
+
+    Ok((l, r))
+}
+
+fn parse_num(chars: &mut Peekable<CharIndices>, line: &str) -> Result<u32, ParseError> {
+    let column = column_at(chars, line);
+    let mut output: Option<u32> = None;
+
+    while let Some((_, ch @ '0'..='9')) = chars.peek().copied() {
+        output = output.unwrap_or(0)
+            .checked_mul(10)
+            .and_then(|o| o.checked_add(ch as u32 - '0' as u32))
+            .map(Some)
+            .ok_or_else(|| ParseError::new(column, "number too large"))?;
+        chars.next();
+    }
+
+    output.ok_or_else(|| ParseError::new(column, "expected a number"))
+}
+
+fn skip_whitespace(chars: &mut Peekable<CharIndices>) {
+    while let Some((_, ch)) = chars.peek() {
+        if !ch.is_whitespace() {
+            break;
+        }
+        chars.next();
     }
+}
+
+// One based column of the next character, or just past the end of the line
+fn column_at(chars: &mut Peekable<CharIndices>, line: &str) -> usize {
+    chars.peek().map(|(i, _)| i + 1).unwrap_or(line.len() + 1)
+}
 
-    output
+impl ParseError {
+    fn new(column: usize, reason: &str) -> ParseError {
+        ParseError { column, reason: reason.to_string() }
+    }
 }
 
 
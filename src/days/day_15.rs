@@ -3,10 +3,11 @@ use std::io::BufReader;
 use std::io::prelude::*;
 
 use std::collections::HashMap;
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::iter::Peekable;
 
 use std::cmp;
+use std::fmt;
 
 use crate::d15::Entity::*;
 
@@ -41,96 +42,121 @@ enum Entity {
     Sesnor,
 }
 
+#[derive(Clone, Copy)]
+enum Axis {
+    Row,
+    Column,
+}
+
+// Sorted inclusive ranges, none overlapping or touching
+struct Intervals {
+    ranges: Vec<(i64, i64)>,
+}
+
+// Inclusive rectangle of cells
+struct Region {
+    min: (i64, i64),
+    max: (i64, i64),
+}
+
+enum Query {
+    Print,
+    Covered(Axis, (i64, i64), Option<(i64, i64)>),
+    Regions((i64, i64), (i64, i64)),
+    Sensors((i64, i64)),
+    Polygon,
+}
+
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
     let sensor_data = new_sesnor_data(
         parse_sesnor_data(file_name)?
     );
 
-    //sensor_data.print(false);
-    //println!();
-    //sensor_data.print(true);
-
-    // Part One
-    let mut score = 0;
-    let disaply = false;
-
-    let y = 2000000;
-    let min_x = sensor_data.top_left.0;
-    let max_x = sensor_data.top_left.0 + sensor_data.width as i64;
-
-    if disaply {
-        sensor_data.print(false);   
+    let mut y = 2000000;
+    let mut bound = 4000000;
+    let mut queries = Vec::new();
+
+    // Usage: cargo run -- 15 [row Y] [bound MAX] [print]
+    //                        [covered rows|columns FROM TO [MIN MAX]]
+    //                        [regions MIN_X MIN_Y MAX_X MAX_Y]
+    //                        [sensors X Y] [polygon]
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter().peekable();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "row" => y = next_num(&mut args),
+            "bound" => bound = next_num(&mut args),
+            "print" => queries.push(Query::Print),
+            "covered" => {
+                let axis = match args.next().map(String::as_str) {
+                    Some("rows") => Axis::Row,
+                    Some("columns") => Axis::Column,
+                    _ => panic!("Expected rows or columns"),
+                };
+                let lines = (next_num(&mut args), next_num(&mut args));
+                let span = match args.peek().map(|a| a.parse::<i64>()) {
+                    Some(Ok(_)) => Some((next_num(&mut args), next_num(&mut args))),
+                    _ => None,
+                };
+                queries.push(Query::Covered(axis, lines, span));
+            },
+            "regions" => {
+                let min = (next_num(&mut args), next_num(&mut args));
+                let max = (next_num(&mut args), next_num(&mut args));
+                queries.push(Query::Regions(min, max));
+            },
+            "sensors" => queries.push(Query::Sensors((next_num(&mut args), next_num(&mut args)))),
+            "polygon" => queries.push(Query::Polygon),
+            _ => panic!("Unknown argument {}", arg),
+        }
     }
 
-    for x in min_x..max_x {
-        if !sensor_data.could_contain_beacon((x, y)) {
-            score += 1;
-        };
-    }
+    // Part One
+    // Beacons are always in range of their own sensor
+    let beacons = sensor_data.entities.iter()
+        .filter(|(pos, entity)| matches!(entity, Beacon) && pos.1 == y)
+        .count() as u64;
 
-    println!("score: {}", score);
+    println!("score: {}", sensor_data.coverage(Axis::Row, y).len() - beacons);
 
     // Part Two
-    let max_y = 4000000;
-    let max_x = 4000000;
-
-    for y in 0..max_y {
-        let line = Line {
-            a: 0,
-            b: 1,
-            c: -y,
-            min_x: 0,
-            max_x: max_x,
-            min_y: y,
-            max_y: y
-        };
-
-        let mut sizes = BTreeMap::new();
-
-
-        print!("y={}: ", y);
-
-        for (_, space) in &sensor_data.sensor_space {
-            if let Some((start, end)) = space.intersects(&line) {
-                print!("{} -> {}, ", start.0, end.0);
-
-                let size = end.0 - start.0;
+    // The distress beacon is the one cell in the bounds no sensor covers
+    for (x, y) in sensor_data.isolated_uncovered((0, 0), (bound, bound)) {
+        println!("solution: {}, {}", x, y);
+        println!("{}", x * 4000000 + y);
+    }
 
-                if size < 0 {
-                    panic!();
+    for query in queries {
+        match query {
+            Query::Print => {
+                sensor_data.print(false);
+                println!();
+                sensor_data.print(true);
+            },
+            Query::Covered(axis, lines, span) =>
+                println!("covered: {}", sensor_data.covered_cells(axis, lines, span)),
+            Query::Regions(min, max) => {
+                for region in sensor_data.uncovered_regions(min, max) {
+                    println!("{}", region);
                 }
-
-                if let Some(old_size) = sizes.get(&start.0) {
-                    if size > *old_size {
-                        sizes.insert(start.0, size);    
-                    }
-                } else {
-                    sizes.insert(start.0, size);
-                } 
-            }
-        }
-
-        println!();
-
-        let mut current_max_x = 0;
-
-        for (x, size) in sizes.iter() {
-            if *x > current_max_x {
-                println!("solution: {}, {}", *x - 1, y);
-                println!("{}", (*x - 1) * 4000000 + y);
-                return Ok(())
-            }
-
-            let next_x = *x + *size;
-
-            if next_x > current_max_x {
-                current_max_x = next_x;
-
-                if current_max_x > max_x {
-                    break;
+            },
+            Query::Sensors(pos) => {
+                println!("{},{} covered by:", pos.0, pos.1);
+                for (sesnor_pos, dist) in sensor_data.sensors_covering(pos) {
+                    println!("  sensor {},{} range {}", sesnor_pos.0, sesnor_pos.1, dist);
                 }
-            }
+            },
+            Query::Polygon => {
+                for ring in sensor_data.coverage_polygon() {
+                    let kind = if signed_area(&ring) > 0 { "outline" } else { "hole" };
+                    let points: Vec<String> = ring.iter()
+                        .map(|(u, v)| format!("{},{}", u, v))
+                        .collect();
+                    println!("{}: {}", kind, points.join(" -> "));
+                }
+            },
         }
     }
 
@@ -138,6 +164,11 @@ pub fn run(file_name: &str) -> std::io::Result<()> {
 }
 
 
+fn next_num<'a, I>(args: &mut I) -> i64 where I: Iterator<Item = &'a String> {
+    args.next().expect("Missing argument").parse().unwrap()
+}
+
+
 fn new_sesnor_data(data: Vec<((i64, i64), (i64, i64))>) -> SesnorData {
     let mut output = SesnorData {
         entities: HashMap::new(),
@@ -200,6 +231,67 @@ impl SesnorData {
     }
 
 
+    fn is_covered(&self, pos: (i64, i64)) -> bool {
+        self.sensor_dist.iter()
+            .any(|(sesnor_pos, dist)| manhattan(pos, *sesnor_pos) <= *dist)
+    }
+
+    // Uncovered cells with every neighbour, diagonals included, either
+    // covered or outside the bounds. Wider uncovered areas are left to
+    // uncovered_regions.
+    //
+    // Each covered neighbour puts a lone cell just outside that sensor's
+    // range, on one of its boundary lines. The cell is where two of those
+    // lines cross, where a line crosses the edge of the bounds, at a
+    // corner of a boundary or of the bounds, or half a cell from where
+    // a line crosses a sensor that's one cell further off
+    fn isolated_uncovered(&self, min: (i64, i64), max: (i64, i64)) -> Vec<(i64, i64)> {
+        let boundaries: Vec<Space> = self.sensor_dist.iter()
+            .map(|(sesnor_pos, dist)| new_sensor_space(*sesnor_pos, *dist as i64 + 1))
+            .collect();
+
+        let edges = [
+            row_line(min.1, min.0, max.0),
+            row_line(max.1, min.0, max.0),
+            column_line(min.0, min.1, max.1),
+            column_line(max.0, min.1, max.1),
+        ];
+
+        let mut candidates = BTreeSet::from([
+            min, max, (min.0, max.1), (max.0, min.1),
+        ]);
+
+        for (i, space) in boundaries.iter().enumerate() {
+            for line in space.lines() {
+                candidates.extend([
+                    (line.min_x, line.min_y), (line.min_x, line.max_y),
+                    (line.max_x, line.min_y), (line.max_x, line.max_y),
+                ]);
+
+                for other in boundaries[i + 1..].iter().flat_map(Space::lines) {
+                    candidates.extend(line.intersects(other).into_iter().flat_map(cells_around));
+                }
+
+                for edge in &edges {
+                    candidates.extend(edge.intersects(line).into_iter().flat_map(cells_around));
+                }
+            }
+        }
+
+        let in_bounds = |p: (i64, i64)|
+            p.0 >= min.0 && p.0 <= max.0 && p.1 >= min.1 && p.1 <= max.1;
+
+        candidates.into_iter()
+            .filter(|p| in_bounds(*p) && !self.is_covered(*p))
+            .filter(|p| {
+                (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (p.0 + dx, p.1 + dy)))
+                    .filter(|n| n != p && in_bounds(*n))
+                    .all(|n| self.is_covered(n))
+            })
+            .collect()
+    }
+
+
     fn add_entity(&mut self, 
         pos: (i64, i64), entity: Entity
     ) {
@@ -270,39 +362,263 @@ impl SesnorData {
 }
 
 
-impl Space {
-    fn intersects(&self, line: &Line) -> Option<((i64, i64), (i64, i64))> {
-        match (self.top_left.intersects(line), self.top_right.intersects(line)) {
-            (Some(p1), Some(p2)) => return Some((p1, p2)),
-            _ => ()
-        };
+/* Queries */
+
+impl SesnorData {
+    // Cells in range of a sensor along one row or column
+    fn coverage(&self, axis: Axis, line: i64) -> Intervals {
+        let ranges = self.sensor_dist.iter()
+            .filter_map(|(sesnor_pos, dist)| {
+                let (along, across) = match axis {
+                    Axis::Row => (sesnor_pos.0, sesnor_pos.1),
+                    Axis::Column => (sesnor_pos.1, sesnor_pos.0),
+                };
+                let reach = *dist as i64 - (across - line).abs();
+
+                if reach < 0 {
+                    return None;
+                }
+
+                Some((along - reach, along + reach))
+            })
+            .collect();
+
+        Intervals::merge(ranges)
+    }
+
+    // Covered cells on every row or column from lines.0 to lines.1,
+    // counting only those between span.0 and span.1 along each one
+    fn covered_cells(&self, axis: Axis, lines: (i64, i64), span: Option<(i64, i64)>) -> u64 {
+        (lines.0..=lines.1)
+            .map(|line| {
+                let coverage = self.coverage(axis, line);
+                match span {
+                    Some((min, max)) => coverage.clip(min, max).len(),
+                    None => coverage.len(),
+                }
+            })
+            .sum()
+    }
+
+    // Gaps in the coverage of each row, with the same gap on consecutive
+    // rows joined into one rectangle
+    fn uncovered_regions(&self, min: (i64, i64), max: (i64, i64)) -> Vec<Region> {
+        let mut regions = Vec::new();
+        let mut open: HashMap<(i64, i64), i64> = HashMap::new();
+
+        for y in min.1..=max.1 {
+            let gaps = self.coverage(Axis::Row, y).gaps(min.0, max.0);
+            let mut next_open = HashMap::new();
+
+            for gap in gaps {
+                let start_y = open.remove(&gap).unwrap_or(y);
+                next_open.insert(gap, start_y);
+            }
+
+            for (gap, start_y) in open {
+                regions.push(Region { min: (gap.0, start_y), max: (gap.1, y - 1) });
+            }
+
+            open = next_open;
+        }
+
+        for (gap, start_y) in open {
+            regions.push(Region { min: (gap.0, start_y), max: (gap.1, max.1) });
+        }
+
+        regions.sort_by_key(|r| (r.min.1, r.min.0));
+        regions
+    }
 
-        match (self.bottom_left.intersects(line), self.bottom_right.intersects(line)) {
-            (Some(p1), Some(p2)) => return Some((p1, p2)),
-            _ => ()
+    // Sensors with the point in range, closest first
+    fn sensors_covering(&self, pos: (i64, i64)) -> Vec<((i64, i64), u32)> {
+        let mut sensors: Vec<((i64, i64), u32)> = self.sensor_dist.iter()
+            .filter(|(sesnor_pos, dist)| manhattan(pos, **sesnor_pos) <= **dist)
+            .map(|(sesnor_pos, dist)| (*sesnor_pos, *dist))
+            .collect();
+
+        sensors.sort_by_key(|(sesnor_pos, _)| (manhattan(pos, *sesnor_pos), *sesnor_pos));
+        sensors
+    }
+
+    // Outline of everything in range, in coordinates rotated 45 degrees
+    // (u = x + y, v = x - y) where every sensor covers a square. Cell
+    // (u, v) spans u to u + 1, so squares sharing an edge join up. Outlines
+    // go anticlockwise and holes clockwise
+    fn coverage_polygon(&self) -> Vec<Vec<(i64, i64)>> {
+        let squares: Vec<((i64, i64), (i64, i64))> = self.sensor_dist.iter()
+            .map(|(p, dist)| {
+                let (u, v, d) = (p.0 + p.1, p.0 - p.1, *dist as i64);
+                ((u - d, v - d), (u + d + 1, v + d + 1))
+            })
+            .collect();
+
+        // Only the edges of squares matter, so the plane is cut along
+        // them into a grid of cells that are either covered or not
+        let mut us: Vec<i64> = squares.iter().flat_map(|(min, max)| [min.0, max.0]).collect();
+        let mut vs: Vec<i64> = squares.iter().flat_map(|(min, max)| [min.1, max.1]).collect();
+        us.sort_unstable();
+        us.dedup();
+        vs.sort_unstable();
+        vs.dedup();
+
+        let covered = |i: usize, j: usize| -> bool {
+            i + 1 < us.len() && j + 1 < vs.len() && squares.iter().any(|(min, max)|
+                min.0 <= us[i] && us[i] < max.0 && min.1 <= vs[j] && vs[j] < max.1)
         };
 
-        None
+        // Every edge between a covered and uncovered cell, directed so
+        // the covered side is on the left
+        let mut edges: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+        let mut add_edge = |from, to| edges.entry(from).or_default().push(to);
+
+        for i in 0..us.len().saturating_sub(1) {
+            for j in 0..vs.len().saturating_sub(1) {
+                if !covered(i, j) {
+                    continue;
+                }
+                if j == 0 || !covered(i, j - 1) {
+                    add_edge((i, j), (i + 1, j));
+                }
+                if !covered(i + 1, j) {
+                    add_edge((i + 1, j), (i + 1, j + 1));
+                }
+                if !covered(i, j + 1) {
+                    add_edge((i + 1, j + 1), (i, j + 1));
+                }
+                if i == 0 || !covered(i - 1, j) {
+                    add_edge((i, j + 1), (i, j));
+                }
+            }
+        }
+
+        let mut rings = Vec::new();
+
+        while let Some(start) = edges.keys().min().copied() {
+            let mut ring = vec![start];
+            let mut current = start;
+
+            loop {
+                let ends = edges.get_mut(&current).unwrap();
+                let next = ends.pop().unwrap();
+
+                if ends.is_empty() {
+                    edges.remove(&current);
+                }
+                if next == start {
+                    break;
+                }
+
+                ring.push(next);
+                current = next;
+            }
+
+            rings.push(corners(&ring).into_iter()
+                .map(|(i, j)| (us[i], vs[j]))
+                .collect());
+        }
+
+        rings
+    }
+}
+
+
+impl Intervals {
+    // Sorts and joins ranges that overlap or touch
+    fn merge(mut ranges: Vec<(i64, i64)>) -> Intervals {
+        ranges.sort_unstable();
+
+        let mut merged: Vec<(i64, i64)> = Vec::new();
+
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+
+        Intervals { ranges: merged }
+    }
+
+    fn clip(&self, min: i64, max: i64) -> Intervals {
+        Intervals {
+            ranges: self.ranges.iter()
+                .map(|(start, end)| ((*start).max(min), (*end).min(max)))
+                .filter(|(start, end)| start <= end)
+                .collect(),
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.ranges.iter().map(|(start, end)| (end - start + 1) as u64).sum()
+    }
+
+    // Ranges between min and max that aren't covered
+    fn gaps(&self, min: i64, max: i64) -> Vec<(i64, i64)> {
+        let mut gaps = Vec::new();
+        let mut next = min;
+
+        for (start, end) in &self.clip(min, max).ranges {
+            if *start > next {
+                gaps.push((next, start - 1));
+            }
+            next = end + 1;
+        }
+
+        if next <= max {
+            gaps.push((next, max));
+        }
+
+        gaps
+    }
+}
+
+
+// Drops points in the middle of straight edges
+fn corners(ring: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    (0..ring.len())
+        .filter(|i| {
+            let prev = ring[(i + ring.len() - 1) % ring.len()];
+            let next = ring[(i + 1) % ring.len()];
+            prev.0 != next.0 && prev.1 != next.1
+        })
+        .map(|i| ring[i])
+        .collect()
+}
+
+// Twice the area, positive when the points go anticlockwise
+fn signed_area(ring: &[(i64, i64)]) -> i64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+
+impl Space {
+    fn lines(&self) -> [&Line; 4] {
+        [&self.top_left, &self.top_right, &self.bottom_left, &self.bottom_right]
     }
 }
 
 
 impl Line {
+    // Where the lines cross, at twice the scale. Diagonals can cross
+    // halfway between cells, which this keeps exact
     fn intersects(&self, other: &Line) -> Option<(i64, i64)> {
-        let x_numerator = self.b * other.c - other.b * self.c;
-        let x_denominator = self.a * other.b - other.a * self.b;
-        let y_numerator = self.c * other.a - other.c * self.a;
-        let y_denominator = self.a * other.b - other.a * self.b;
+        let denominator = self.a * other.b - other.a * self.b;
 
-        if x_denominator == 0 || y_denominator == 0 {
+        if denominator == 0 {
             return None;
         }
 
-        let x = x_numerator / x_denominator;
-        let y = y_numerator / y_denominator;
+        let x = 2 * (self.b * other.c - other.b * self.c) / denominator;
+        let y = 2 * (self.c * other.a - other.c * self.a) / denominator;
 
-        if x < self.min_x || x > self.max_x || 
-           y < self.min_y || y > self.max_y {
+        if x < 2 * self.min_x || x > 2 * self.max_x ||
+           y < 2 * self.min_y || y > 2 * self.max_y {
             return None;
         }
 
@@ -311,6 +627,15 @@ impl Line {
 }
 
 
+// Cells touching a point given at twice the scale
+fn cells_around(p: (i64, i64)) -> impl Iterator<Item = (i64, i64)> {
+    let xs = (p.0.div_euclid(2), (p.0 + 1).div_euclid(2));
+    let ys = (p.1.div_euclid(2), (p.1 + 1).div_euclid(2));
+
+    [(xs.0, ys.0), (xs.0, ys.1), (xs.1, ys.0), (xs.1, ys.1)].into_iter()
+}
+
+
 fn new_sensor_space(p: (i64, i64), dist_to_beacon: i64) -> Space {
     let top = (p.0, p.1 + dist_to_beacon);
     let left = (p.0 - dist_to_beacon, p.1);
@@ -337,9 +662,13 @@ fn new_line(p1: (i64, i64), p2: (i64, i64)) -> Line {
         panic!();
     }
 
-    let a = slope_denominator;
-    let b = -slope_numerator;
-    let c = -intercept_numerator;
+    // Sensor boundaries are diagonals, so dividing through leaves a and b
+    // at 1 or -1 and keeps the products in intersects small
+    let scale = gcd(slope_denominator.abs(), slope_numerator.abs());
+
+    let a = slope_denominator / scale;
+    let b = -slope_numerator / scale;
+    let c = -intercept_numerator / scale;
 
     Line {
         a: a,
@@ -353,6 +682,26 @@ fn new_line(p1: (i64, i64), p2: (i64, i64)) -> Line {
 }
 
 
+// Horizontal line at y between two x values
+fn row_line(y: i64, min_x: i64, max_x: i64) -> Line {
+    Line { a: 0, b: 1, c: -y, min_x, max_x, min_y: y, max_y: y }
+}
+
+// Vertical line at x between two y values
+fn column_line(x: i64, min_y: i64, max_y: i64) -> Line {
+    Line { a: 1, b: 0, c: -x, min_x: x, max_x: x, min_y, max_y }
+}
+
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{} to {},{} ({} cells)",
+            self.min.0, self.min.1, self.max.0, self.max.1,
+            (self.max.0 - self.min.0 + 1) * (self.max.1 - self.min.1 + 1))
+    }
+}
+
+
 /* Parsing */
 fn parse_sesnor_data(file_name: &str) -> std::io::Result<Vec<((i64, i64), (i64, i64))>> {
     let file = fs::File::open(file_name)
@@ -448,6 +797,10 @@ fn length(n: i64, base: u32) -> u32 {
 }
 
 
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
 fn manhattan(p1: (i64, i64), p2: (i64, i64)) -> u32 {
     (p1.0 - p2.0).abs() as u32 + (p1.1 - p2.1).abs() as u32   
 }
\ No newline at end of file
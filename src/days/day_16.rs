@@ -5,6 +5,7 @@ use std::io::prelude::*;
 use std::iter::Peekable;
 
 use std::collections::HashMap;
+use std::cmp::Reverse;
 use bit_vec::BitVec;
 
 type NodeIndex = usize;
@@ -28,6 +29,47 @@ struct EdgeData {
     next_outgoing_edge: Option<EdgeIndex>,
 }
 
+// Valve opened by an agent, releasing pressure from the next minute on
+#[derive(Clone)]
+struct Opening {
+    valve: NodeIndex,
+    minute: u32,
+}
+
+// Best way found for one agent to open exactly a set of valves
+struct Plan {
+    score: u32,
+    openings: Vec<Opening>,
+}
+
+// Valves each agent opens and the pressure released between them
+struct Schedule {
+    score: u32,
+    agents: Vec<Vec<Opening>>,
+    stats: SearchStats,
+}
+
+#[derive(Clone, Copy, Default)]
+struct SearchStats {
+    explored: u64,
+    pruned: u64,
+}
+
+struct Search<'graph> {
+    graph: &'graph Graph,
+    minutes: u32,
+    prune: bool,
+    open_valves: BitVec,
+    route: Vec<Opening>,
+    score_map: HashMap<BitVec, Plan>,
+    best: Plan,
+    // Valves worth opening, biggest flow first
+    by_flow: Vec<NodeIndex>,
+    // Shortest walk between any two valves
+    min_distance: u32,
+    stats: SearchStats,
+}
+
 struct Successors<'graph> {
     graph: &'graph Graph,
     currend_edge_index: Option<EdgeIndex>,
@@ -36,93 +78,299 @@ struct Successors<'graph> {
 
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
-    let graph = build_graph(file_name)?;
+    let raw_graph = build_graph(file_name)?;
+    let graph = raw_graph.compress();
+
+    // Usage: cargo run -- 16 [agents K] [minutes N] [dot raw|compressed]
+    //                        [exhaustive]
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter();
+    let mut agents = None;
+    let mut minutes = None;
+    let mut dot = None;
+    let mut exhaustive = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "agents" => agents = Some(args.next().expect("Missing argument").parse().unwrap()),
+            "minutes" => minutes = Some(args.next().expect("Missing argument").parse().unwrap()),
+            "dot" => dot = Some(args.next().expect("Missing argument").clone()),
+            "exhaustive" => exhaustive = true,
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
+    if let Some(which) = dot {
+        let schedule = optimize(&graph, agents.unwrap_or(1), minutes.unwrap_or(30), exhaustive);
+        let routes = schedule_routes(&graph, &schedule);
+
+        match which.as_str() {
+            "raw" => print!("{}", raw_graph.to_dot(&routes)),
+            "compressed" => print!("{}", graph.to_dot(&routes)),
+            _ => panic!("Expected raw or compressed"),
+        }
+
+        return Ok(());
+    }
+
+    match (agents, minutes) {
+        (None, None) => {
+            let schedule = optimize(&graph, 1, 30, exhaustive);
+            println!("part one: {}", schedule.score);
+            print_schedule(&graph, &schedule);
+
+            let schedule = optimize(&graph, 2, 26, exhaustive);
+            println!("part two: {}", schedule.score);
+            print_schedule(&graph, &schedule);
+        },
+        _ => {
+            let (agents, minutes) = (agents.unwrap_or(1), minutes.unwrap_or(30));
+            let schedule = optimize(&graph, agents, minutes, exhaustive);
+            println!("{} agents, {} minutes: {}", agents, minutes, schedule.score);
+            print_schedule(&graph, &schedule);
+        },
+    }
 
-    part_one(&graph);
-    part_two(&graph);
-    
     Ok(())
 }
 
 
-fn part_one(graph: &Graph) {
-    let mut open_valves = BitVec::from_elem(graph.nodes.len(), false);
-    let mut score_map = HashMap::new();
+// Agents work independently so the best they can do together is the best
+// split of the valves between them. One search finds the best a single
+// agent can do with each set of valves, then each agent added takes the
+// set that best complements the rest
+fn optimize(graph: &Graph, agents: usize, minutes: u32, exhaustive: bool) -> Schedule {
+    // Splitting valves between agents needs the best score for every set,
+    // so branches can only be cut when there's a single agent
+    let single = agents <= 1;
+    let mut search = Search::new(graph, minutes, single && !exhaustive);
 
-    let score = dfs(
-        graph, *graph.id_node_map.get(&0).unwrap(), 0, 30, &mut open_valves, &mut score_map
-    );
+    search.dfs(*graph.id_node_map.get(&0).unwrap(), 0, minutes);
 
-    println!("{}", score);
-}
+    if single {
+        return Schedule {
+            score: search.best.score,
+            agents: vec![search.best.openings],
+            stats: search.stats,
+        };
+    }
+
+    // Valves worth opening, a set of them is a bit mask over this list
+    let useful: Vec<NodeIndex> = (0..graph.nodes.len())
+        .filter(|node| graph.nodes[*node].flow_rate > 0)
+        .collect();
+    let sets = 1 << useful.len();
+
+    let plans: HashMap<usize, &Plan> = search.score_map.iter()
+        .map(|(set, plan)| (to_mask(set, &useful), plan))
+        .collect();
+
+    // Best plan using only valves from each set, as (score, plan set)
+    let mut within = vec![(0, 0); sets];
+
+    for (set, plan) in &plans {
+        within[*set] = (plan.score, *set);
+    }
+
+    for bit in 0..useful.len() {
+        for set in 0..sets {
+            if set & (1 << bit) != 0 && within[set ^ (1 << bit)].0 > within[set].0 {
+                within[set] = within[set ^ (1 << bit)];
+            }
+        }
+    }
+
+    // Best score for the agents so far within each set, and the subset
+    // each added agent took to get it
+    let mut best: Vec<u32> = within.iter().map(|w| w.0).collect();
+    let mut choices = Vec::new();
 
-fn part_two(graph: &Graph) {
-    let mut open_valves = BitVec::from_elem(graph.nodes.len(), false);
-    let mut score_map = HashMap::new();
+    for _ in 1..agents {
+        let mut next_best = vec![0; sets];
+        let mut choice = vec![0; sets];
 
-    dfs(
-        graph, *graph.id_node_map.get(&0).unwrap(), 
-        0, 26, &mut open_valves, &mut score_map
-    );
+        for set in 0..sets {
+            // Walk every subset of set
+            let mut subset = set;
 
-    let mut max_score = 0;
+            loop {
+                let score = within[subset].0 + best[set ^ subset];
 
-    for (set1, score1) in &score_map {
-        for (set2, score2) in &score_map {
-            let score = score1 + score2;
+                if score > next_best[set] {
+                    next_best[set] = score;
+                    choice[set] = subset;
+                }
 
-            if score > max_score && no_overlap(set1, set2) {
-                max_score = score;
+                if subset == 0 {
+                    break;
+                }
+                subset = (subset - 1) & set;
             }
         }
+
+        best = next_best;
+        choices.push(choice);
+    }
+
+    let mut remaining = sets - 1;
+    let mut schedule = Schedule { score: best[remaining], agents: Vec::new(), stats: search.stats };
+
+    for choice in choices.iter().rev() {
+        let subset = choice[remaining];
+        schedule.agents.push(plans[&within[subset].1].openings.clone());
+        remaining ^= subset;
     }
 
-    println!("{}", max_score);
+    schedule.agents.push(plans[&within[remaining].1].openings.clone());
+    schedule.agents.reverse();
+    schedule
 }
 
-fn no_overlap(b1: &BitVec, b2: &BitVec) -> bool {
-    let mut b1_c = b1.clone();
-    b1_c.and(b2);
-    b1_c.none()
+fn to_mask(set: &BitVec, useful: &[NodeIndex]) -> usize {
+    useful.iter()
+        .enumerate()
+        .filter(|(_, node)| set[**node])
+        .fold(0, |mask, (bit, _)| mask | 1 << bit)
 }
 
-fn dfs(
-        graph: &Graph, current_node: NodeIndex, current_score: u32, 
-        time_remaning: u32, open_valves: &mut BitVec, score_map: &mut HashMap<BitVec, u32>
-) -> u32 {
-    let max_child = graph.successors(current_node).into_iter().filter_map(|next_node| {
-        if open_valves[next_node] || time_remaning <= graph.get_distance(current_node, next_node).unwrap() {
-            if let Some(score) = score_map.get(open_valves) {
-                if *score < current_score {
-                    score_map.insert(open_valves.clone(), current_score);
+fn print_schedule(graph: &Graph, schedule: &Schedule) {
+    println!("  {}", schedule.stats);
+
+    for (agent, openings) in schedule.agents.iter().enumerate() {
+        for opening in openings {
+            let node = &graph.nodes[opening.valve];
+            println!("  agent {} opens {} at minute {}",
+                agent + 1, node, opening.minute);
+        }
+    }
+}
+
+// Valve ids each agent passes through, starting from AA
+fn schedule_routes(graph: &Graph, schedule: &Schedule) -> Vec<Vec<u32>> {
+    schedule.agents.iter()
+        .map(|openings| std::iter::once(0)
+            .chain(openings.iter().map(|opening| graph.nodes[opening.valve].id))
+            .collect())
+        .collect()
+}
+
+
+impl<'graph> Search<'graph> {
+    fn new(graph: &'graph Graph, minutes: u32, prune: bool) -> Search<'graph> {
+        let mut by_flow: Vec<NodeIndex> = (0..graph.nodes.len())
+            .filter(|node| graph.nodes[*node].flow_rate > 0)
+            .collect();
+        by_flow.sort_by_key(|node| Reverse(graph.nodes[*node].flow_rate));
+
+        let min_distance = graph.shortest_distance_map.iter()
+            .filter(|((source, target), _)| source != target)
+            .map(|(_, distance)| *distance)
+            .min()
+            .unwrap_or(1);
+
+        Search {
+            graph,
+            minutes,
+            prune,
+            open_valves: BitVec::from_elem(graph.nodes.len(), false),
+            route: Vec::new(),
+            score_map: HashMap::new(),
+            best: Plan { score: 0, openings: Vec::new() },
+            by_flow,
+            min_distance,
+            stats: SearchStats::default(),
+        }
+    }
+
+    // Tries every order of opening valves from the current one, keeping
+    // the best plan for each set of valves opened along the way. When
+    // pruning, only the best plan overall is kept and branches that can't
+    // beat it are cut
+    fn dfs(&mut self, current_node: NodeIndex, current_score: u32, time_remaning: u32) {
+        self.stats.explored += 1;
+        self.record(current_score);
+
+        let graph = self.graph;
+
+        // Biggest gains first so good scores are found early on
+        let mut moves: Vec<(NodeIndex, u32, u32)> = graph.successors(current_node)
+            .filter_map(|next_node| {
+                let distance = graph.get_distance(current_node, next_node).unwrap();
+
+                if self.open_valves[next_node] || time_remaning <= distance {
+                    return None;
                 }
-            } else {
-                score_map.insert(open_valves.clone(), current_score);
+
+                let time_remaning = time_remaning - distance - 1;
+                Some((next_node, time_remaning, time_remaning * graph.nodes[next_node].flow_rate))
+            })
+            .collect();
+        moves.sort_by_key(|(_, _, aditional_score)| Reverse(*aditional_score));
+
+        for (next_node, time_remaning, aditional_score) in moves {
+            let score = current_score + aditional_score;
+
+            self.open_valves.set(next_node, true);
+
+            if self.prune && score + self.upper_bound(time_remaning) <= self.best.score {
+                self.open_valves.set(next_node, false);
+                self.stats.pruned += 1;
+                continue;
+            }
+
+            self.route.push(Opening { valve: next_node, minute: self.minutes - time_remaning });
+            self.dfs(next_node, score, time_remaning);
+            self.route.pop();
+
+            self.open_valves.set(next_node, false);
+        }
+    }
+
+    // Most pressure the closed valves could still release, if the biggest
+    // were opened first and every one was as close as the closest pair
+    fn upper_bound(&self, time_remaning: u32) -> u32 {
+        let step = self.min_distance + 1;
+        let mut time = time_remaning;
+        let mut bound = 0;
+
+        for valve in self.by_flow.iter().filter(|valve| !self.open_valves[**valve]) {
+            if time <= step {
+                break;
             }
-            return None;
+
+            time -= step;
+            bound += time * self.graph.nodes[*valve].flow_rate;
         }
 
-        open_valves.set(next_node, true);
-        
-        let time_remaning = (time_remaning - graph.get_distance(current_node, next_node).unwrap()) - 1;
-        let aditional_score = time_remaning * graph.nodes[next_node].flow_rate;
-        let score = dfs(
-            graph, next_node, current_score + aditional_score, 
-            time_remaning, open_valves, score_map
-        );
+        bound
+    }
 
-        open_valves.set(next_node, false);
+    fn record(&mut self, score: u32) {
+        if score > self.best.score {
+            self.best = Plan { score, openings: self.route.clone() };
+        }
 
-        Some(score)
-    }).max();
+        if self.prune {
+            return;
+        }
 
-    if let Some(score) = max_child {
-        if score > current_score {
-            return score;
+        match self.score_map.get(&self.open_valves) {
+            Some(plan) if plan.score >= score => (),
+            _ => {
+                self.score_map.insert(self.open_valves.clone(), Plan {
+                    score,
+                    openings: self.route.clone(),
+                });
+            },
         }
-    } 
+    }
+}
 
-    current_score
+
+impl fmt::Display for SearchStats {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "explored {} states, pruned {} branches", self.explored, self.pruned)
+    }
 }
 
 
@@ -176,51 +424,7 @@ fn build_graph(file_name: &str) -> std::io::Result<Graph> {
         }
     }
 
-    let mut output_graph = Graph {
-        nodes: Vec::new(),
-        edges: Vec::new(),
-        id_node_map: HashMap::new(),
-        shortest_distance_map: HashMap::new(),
-    };
-
-    let mut old_to_new_map = HashMap::new();
-
-    for (old_node_i, old_node) in graph.nodes.iter().enumerate() {
-        if old_node.flow_rate == 0 && old_node.id != 0 {
-            continue;
-        }
-
-        let new_i = output_graph.add_node(old_node.id, old_node.flow_rate);
-        old_to_new_map.insert(old_node_i, new_i);
-    }
-
-    for old_node_i in 0..graph.nodes.len() {
-        if !old_to_new_map.contains_key(&old_node_i) {
-            continue;
-        }
-
-        for old_target_i in 0..graph.nodes.len() {
-            if !old_to_new_map.contains_key(&old_target_i) || old_node_i == old_target_i ||   
-                graph.nodes[old_target_i].flow_rate == 0 {
-                continue;
-            }
-
-            let new_source = *old_to_new_map.get(&old_node_i).unwrap();
-            let new_target = *old_to_new_map.get(&old_target_i).unwrap(); 
-
-            output_graph.add_edge(new_source, new_target);
-            output_graph.shortest_distance_map.insert(
-                (new_source, new_target), graph.get_distance(
-                    old_node_i, old_target_i, 
-                ).unwrap()
-            );
-        }
-    }
-
-
-    output_graph.print();
-
-    Ok(output_graph)
+    Ok(graph)
 }
 
 
@@ -281,17 +485,136 @@ impl Graph {
         }
     }
 
-    fn print(&self) {
-        println!("graph: ");
-        for (node_i, node) in self.nodes.iter().enumerate() {
-            println!("  {} fr {}: ", node, node.flow_rate);
-            for (next_i, next_node) in self.nodes.iter().enumerate() {
-                if let Some(dist) = self.get_distance(node_i, next_i) {
-                    println!("    {} -> {} fr {}", dist, next_node, next_node.flow_rate);
+    // Graph of only the valves worth opening, plus the start, where every
+    // edge stands for the shortest path to that valve through the dropped
+    // zero flow valves and is weighted by its length
+    fn compress(&self) -> Graph {
+        let mut output_graph = Graph {
+            nodes: Vec::new(),
+            edges: Vec::new(),
+            id_node_map: HashMap::new(),
+            shortest_distance_map: HashMap::new(),
+        };
+
+        // Kept in valve order so edges, and so ties in the search, come
+        // out the same on every run
+        let mut old_to_new = Vec::new();
+
+        for (old_node_i, old_node) in self.nodes.iter().enumerate() {
+            if old_node.flow_rate == 0 && old_node.id != 0 {
+                continue;
+            }
+
+            let new_i = output_graph.add_node(old_node.id, old_node.flow_rate);
+            old_to_new.push((old_node_i, new_i));
+            output_graph.shortest_distance_map.insert((new_i, new_i), 0);
+        }
+
+        for (old_node_i, new_source) in &old_to_new {
+            for (old_target_i, new_target) in &old_to_new {
+                if old_node_i == old_target_i || self.nodes[*old_target_i].flow_rate == 0 {
+                    continue;
                 }
+
+                output_graph.add_edge(*new_source, *new_target);
+                output_graph.shortest_distance_map.insert(
+                    (*new_source, *new_target),
+                    self.get_distance(*old_node_i, *old_target_i).unwrap()
+                );
             }
-            println!();
         }
+
+        output_graph
+    }
+
+    // Graphviz graph of the valves with their flow rates. Each route is
+    // a list of valve ids, the tunnels along it and the valves it opens
+    // are drawn in a colour per route
+    fn to_dot(&self, routes: &[Vec<u32>]) -> String {
+        const COLOURS: [&str; 6] = ["red", "blue", "darkgreen", "orange", "purple", "brown"];
+
+        let mut node_colours = HashMap::new();
+        let mut edge_colours = HashMap::new();
+
+        for (route, colour) in routes.iter().zip(COLOURS.iter().cycle()) {
+            let nodes: Vec<NodeIndex> = route.iter()
+                .map(|id| *self.id_node_map.get(id).unwrap())
+                .collect();
+
+            for node in nodes.iter().skip(1) {
+                node_colours.insert(*node, *colour);
+            }
+
+            for pair in nodes.windows(2) {
+                for (from, to) in self.route_edges(pair[0], pair[1]) {
+                    edge_colours.insert((from.min(to), from.max(to)), *colour);
+                }
+            }
+        }
+
+        let mut output = String::from("graph valves {\n");
+
+        for (node_i, node) in self.nodes.iter().enumerate() {
+            output += &format!("    {} [label=\"{}\\n{}\"", node, node, node.flow_rate);
+
+            match node_colours.get(&node_i) {
+                Some(colour) => output += &format!(", color={}, style=bold", colour),
+                None if node.flow_rate == 0 => output += ", color=grey",
+                None => (),
+            }
+
+            output += "];\n";
+        }
+
+        // Tunnels go both ways so each pair is drawn once
+        let mut pairs: Vec<(NodeIndex, NodeIndex)> = (0..self.nodes.len())
+            .flat_map(|node_i| self.successors(node_i).map(move |next| (node_i.min(next), node_i.max(next))))
+            .collect();
+        pairs.sort_unstable();
+        pairs.dedup();
+
+        for (from, to) in pairs {
+            let mut attributes = Vec::new();
+
+            let distance = self.get_distance(from, to).unwrap();
+            if distance != 1 {
+                attributes.push(format!("label=\"{}\"", distance));
+            }
+
+            if let Some(colour) = edge_colours.get(&(from, to)) {
+                attributes.push(format!("color={}, penwidth=3", colour));
+            }
+
+            output += &format!("    {} -- {}", self.nodes[from], self.nodes[to]);
+            if !attributes.is_empty() {
+                output += &format!(" [{}]", attributes.join(", "));
+            }
+            output += ";\n";
+        }
+
+        output += "}\n";
+        output
+    }
+
+    // Edges along a shortest path between two nodes
+    fn route_edges(&self, from: NodeIndex, to: NodeIndex) -> Vec<(NodeIndex, NodeIndex)> {
+        let mut edges = Vec::new();
+        let mut current = from;
+
+        while current != to {
+            let remaining = self.get_distance(current, to).unwrap();
+            let next = self.successors(current)
+                .find(|next| matches!(
+                    (self.get_distance(current, *next), self.get_distance(*next, to)),
+                    (Some(d1), Some(d2)) if d1 + d2 == remaining
+                ))
+                .unwrap();
+
+            edges.push((current, next));
+            current = next;
+        }
+
+        edges
     }
 }
 
@@ -1,16 +1,16 @@
 use std::fs;
 use std::fmt;
-use std::io::BufReader;
-use std::io::prelude::*;
 use std::collections::HashSet;
 use std::collections::HashMap;
 
 use crate::days::day_17::JetDirection::*;
-use crate::days::day_17::RockType::*;
+
+const DEFAULT_ROCKS: &str = "./src/input/day_17_rocks.txt";
 
 struct Chamber {
     falling_rocks: HashSet<(u64, u64)>, 
     stationery_rocks: HashSet<(u64, u64)>,
+    width: u64,
     height: u64,
     prune_height: u64,
 }
@@ -21,8 +21,16 @@ enum JetDirection {
     Right,
 }
 
-struct RockTypeIterator {
-    position_in_enum: usize,
+// Cells of a rock relative to its bottom left corner
+#[derive(Clone)]
+struct RockShape {
+    cells: Vec<(u64, u64)>,
+    width: u64,
+}
+
+struct RockIterator {
+    position_in_shapes: usize,
+    shapes: Vec<RockShape>,
 }
 
 struct JetIterator {
@@ -30,86 +38,135 @@ struct JetIterator {
     jets: Vec<JetDirection>,
 }
 
-#[derive(Copy, Clone)]
-enum RockType {
-    Horizontal,
-    Vertical,
-    Plus,
-    Corner,
-    Square,
+
+// Everything that decides how the next rocks fall: which rock and jet
+// come next and how far below the top each column is filled
+#[derive(PartialEq, Eq, Hash)]
+struct State {
+    rock_index: usize,
+    jet_index: usize,
+    skyline: Vec<u64>,
+}
+
+// Once the chamber gets back to an earlier state the same rocks keep
+// falling the same way, adding the same height every time round
+struct Cycle {
+    start: usize,
+    length: usize,
+    height_gain: u64,
+}
+
+struct Simulation {
+    // Height of the tower after each number of rocks
+    heights: Vec<u64>,
+    cycle: Option<Cycle>,
 }
 
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
+    let mut width = 7;
+    let mut rocks_file = String::from(DEFAULT_ROCKS);
+    let mut counts = Vec::new();
+
+    // Usage: cargo run -- 17 [width W] [rocks FILE] [count N]...
+    let args: Vec<String> = std::env::args().skip(2).collect();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut next = || args.next().expect("Missing argument");
+
+        match arg.as_str() {
+            "width" => width = next().parse().unwrap(),
+            "rocks" => rocks_file = next().clone(),
+            "count" => counts.push(next().parse().unwrap()),
+            _ => panic!("Unknown argument {}", arg),
+        }
+    }
+
     let directions = parse_jet_directions(file_name)?;
+    let shapes = parse_rock_shapes(&rocks_file)?;
+
+    // Rocks appear two units from the left wall
+    if let Some(shape) = shapes.iter().find(|shape| shape.width + 2 > width) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("a rock {} wide doesn't fit a chamber {} wide", shape.width, width)
+        ));
+    }
 
-    part_one(&directions, 1000000000000, false, true, true);
+    if counts.is_empty() {
+        counts = vec![2022, 1000000000000];
+    }
 
-    Ok(())
-}
+    let max_rocks = *counts.iter().max().unwrap();
+    let simulation = simulate(&directions, &shapes, width, max_rocks, false, true);
 
-fn part_one(jets: &Vec<JetDirection>, max_rocks: usize, debug: bool, prune: bool, memo: bool) {
-    let mut old_height_memoisiation = HashMap::new();
-    let mut delta_memoisiation = HashMap::new();
-    let mut loopable = HashSet::new();
-    let mut chamber = Chamber::new();
-    let mut jets_iter = JetIterator::new(jets).into_iter();
-    let mut jet_index = 0;
-    let mut rock_num_offset = 0;
+    if let Some(cycle) = &simulation.cycle {
+        println!("cycle: starts after {} rocks, repeats every {} rocks, gaining {} height",
+            cycle.start, cycle.length, cycle.height_gain);
+    }
 
-    for (rock_num, (rock, rock_index)) in RockType::value_iter().into_iter().enumerate() {
-        let index = (rock_index.clone(), jet_index.clone());
+    for count in counts {
+        println!("height after {} rocks: {}", count, simulation.height_after(count).unwrap());
+    }
 
-        if memo && delta_memoisiation.contains_key(&index) && loopable.contains(&index) {
-            let (height_inc, num_inc) = delta_memoisiation.get(&index).unwrap();
-            let number_of_increases = (max_rocks as u64 - rock_num_offset as u64 - rock_num as u64) / *num_inc as u64;
+    Ok(())
+}
 
-            rock_num_offset += *num_inc * number_of_increases as usize;
-            chamber.prune_height += *height_inc as u64 * number_of_increases;
+// Drops rocks until the chamber repeats a state, or until max_rocks have
+// fallen if that comes first
+fn simulate(
+    jets: &Vec<JetDirection>, shapes: &[RockShape], width: u64,
+    max_rocks: usize, debug: bool, prune: bool
+) -> Simulation {
+    let mut seen = HashMap::new();
+    let mut chamber = Chamber::new(width);
+    let mut jets_iter = JetIterator::new(jets);
+    let mut jet_index = 0;
+    let mut simulation = Simulation { heights: vec![0], cycle: None };
 
-            println!("{}", number_of_increases);
+    for (rock_num, (rock, rock_index)) in RockIterator::new(shapes).enumerate() {
+        if rock_num >= max_rocks {
+            break;
         }
 
+        let state = State { rock_index, jet_index, skyline: chamber.skyline() };
 
-        if rock_num + rock_num_offset >= max_rocks {
+        if let Some(start) = seen.insert(state, rock_num) {
+            simulation.cycle = Some(Cycle {
+                start,
+                length: rock_num - start,
+                height_gain: simulation.heights[rock_num] - simulation.heights[start],
+            });
             break;
         }
 
-        if (rock_num + rock_num_offset) % 4096 == 0 {
-            println!("Rock: {}", rock_num);
-        }
-
-        chamber.add_new_rock(rock);
-        let next_jet_index = (chamber.simulate_rock_fall(&mut jets_iter, prune) + 1) % jets.len();
+        chamber.add_new_rock(&rock);
+        jet_index = (chamber.simulate_rock_fall(&mut jets_iter, prune) + 1) % jets.len();
 
         if debug {
             chamber.print();
         }
 
+        simulation.heights.push(chamber.height + chamber.prune_height);
+    }
 
-        // Memoisation
-        let height = chamber.height + chamber.prune_height;
+    simulation
+}
 
-        if old_height_memoisiation.contains_key(&index) {
-            let (old_height, old_rock_num) = old_height_memoisiation.get(&index).unwrap();
-            let (height_delta, num_delta) = (height - old_height, rock_num - old_rock_num);
 
-            if delta_memoisiation.contains_key(&index) {
-                let (old_height_delta, old_num_delta) = delta_memoisiation.get(&index).unwrap();
-                if height_delta == *old_height_delta && num_delta == *old_num_delta {
-                    loopable.insert(index);
-                }
-            } else {
-                delta_memoisiation.insert(index, (height_delta, num_delta));
-            }
+impl Simulation {
+    fn height_after(&self, rocks: usize) -> Option<u64> {
+        if let Some(height) = self.heights.get(rocks) {
+            return Some(*height);
         }
-        
-        old_height_memoisiation.insert(index, (height, rock_num));
 
-        jet_index = next_jet_index;
-    }
+        let cycle = self.cycle.as_ref()?;
+        let cycles = (rocks - cycle.start) / cycle.length;
+        let offset = (rocks - cycle.start) % cycle.length;
 
-    println!("max height: {}", chamber.height + chamber.prune_height);
+        Some(self.heights[cycle.start + offset] + cycles as u64 * cycle.height_gain)
+    }
 }
 
 
@@ -193,7 +250,7 @@ impl Chamber {
     }
 
     fn is_prunable_below_y(&self, new_lower_y: u64) -> bool {
-        for x in 0..7 {
+        for x in 0..self.width {
             let y1 = new_lower_y;
             let y2 = new_lower_y + 1;
 
@@ -206,8 +263,19 @@ impl Chamber {
         true
     }
 
-    fn add_new_rock(&mut self, rock: RockType) {
-        for (x, y) in rock.get_positions().iter() {
+    // How far below the top of the tower each column is filled to
+    fn skyline(&self) -> Vec<u64> {
+        let mut tops = vec![0; self.width as usize];
+
+        for (x, y) in &self.stationery_rocks {
+            tops[*x as usize] = tops[*x as usize].max(y + 1);
+        }
+
+        tops.iter().map(|top| self.height - top).collect()
+    }
+
+    fn add_new_rock(&mut self, rock: &RockShape) {
+        for (x, y) in rock.cells.iter() {
             let x = x + 2;
             let y = self.height + 3 + y;
 
@@ -222,7 +290,7 @@ impl Chamber {
             let new_x = (*rock_x as i64) + x_offset;
             let new_y = (*rock_y as i64) + y_offset;
 
-            if new_x < 0 || new_y < 0 || new_x >= 7 {
+            if new_x < 0 || new_y < 0 || new_x >= self.width as i64 {
                 return false;
             }
 
@@ -247,24 +315,6 @@ impl Chamber {
 }
 
 
-impl RockType {
-    fn get_positions(&self) -> Vec<(u64, u64)> {
-        match self {
-            Horizontal => vec![(0, 0), (1, 0), (2, 0), (3, 0)],
-            Vertical => vec![(0, 0), (0, 1), (0, 2), (0, 3)],
-            Plus => vec![(0, 1), (1, 0), (1, 1), (1, 2), (2, 1)],
-            Corner => vec![(0, 0), (1, 0), (2, 0), (2, 1), (2, 2)],
-            Square => vec![(0, 0), (1, 0), (0, 1), (1, 1)],
-        }
-    }
-
-    fn value_iter() -> RockTypeIterator {
-        RockTypeIterator {
-            position_in_enum: 5,
-        }
-    }
-}
-
 /* Debug */
 
 impl fmt::Display for JetDirection {
@@ -279,10 +329,11 @@ impl fmt::Display for JetDirection {
 }
 
 impl Chamber {
-    fn new() -> Chamber {
+    fn new(width: u64) -> Chamber {
         Chamber {
             falling_rocks: HashSet::new(),
             stationery_rocks: HashSet::new(),
+            width,
             height: 0,
             prune_height: 0,
         }
@@ -292,7 +343,7 @@ impl Chamber {
         // Print content 
         for y in (0..(self.height + 8)).rev() {
             print!("|");
-            for x in 0..7 {
+            for x in 0..self.width {
                 if self.stationery_rocks.contains(&(x, y)) {
                     print!("#");
                 } else if self.falling_rocks.contains(&(x, y)) {
@@ -305,26 +356,31 @@ impl Chamber {
         }
         
         // print bottom 
-        println!("+-------+");
+        println!("+{:-<1$}+", "", self.width as usize);
     }
 }
 
 
-impl Iterator for RockTypeIterator {
-    type Item = (RockType, usize);
-
-    fn next(&mut self) -> Option<(RockType, usize)> {
-        static TYPES: [RockType; 5] = [
-            Horizontal, Plus, Corner, Vertical, Square
-        ];
+impl Iterator for RockIterator {
+    type Item = (RockShape, usize);
 
-        self.position_in_enum += 1;
+    fn next(&mut self) -> Option<(RockShape, usize)> {
+        self.position_in_shapes += 1;
 
-        if self.position_in_enum >= 5 {
-            self.position_in_enum = 0;
+        if self.position_in_shapes >= self.shapes.len() {
+            self.position_in_shapes = 0;
         } 
 
-        Some((TYPES[self.position_in_enum], self.position_in_enum))
+        Some((self.shapes[self.position_in_shapes].clone(), self.position_in_shapes))
+    }
+}
+
+impl RockIterator {
+    fn new(shapes: &[RockShape]) -> RockIterator {
+        RockIterator {
+            shapes: shapes.to_vec(),
+            position_in_shapes: shapes.len() - 1,
+        }
     }
 }
 
@@ -355,15 +411,10 @@ impl JetIterator {
 /* Parsing */
 
 fn parse_jet_directions(file_name: &str) -> std::io::Result<Vec<JetDirection>>{
-    let file = fs::File::open(file_name)?;
-    let mut reader = BufReader::new(file);
-    let mut line = String::new();
-
-    reader.read_line(&mut line)?;
-
+    let input = fs::read_to_string(file_name)?;
     let mut output = Vec::new();
 
-    for ch in line.chars() {
+    for ch in input.chars() {
         match ch {
             '<' => output.push(Left),
             '>' => output.push(Right),
@@ -371,6 +422,12 @@ fn parse_jet_directions(file_name: &str) -> std::io::Result<Vec<JetDirection>>{
         };
     }
 
+    if output.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData, "no jet directions"
+        ));
+    }
+
     print!("Jet Directions: ");
     for dir in &output {
         print!("{}", dir);
@@ -378,4 +435,64 @@ fn parse_jet_directions(file_name: &str) -> std::io::Result<Vec<JetDirection>>{
     println!();
 
     Ok(output)
-}   
\ No newline at end of file
+}
+
+// Rocks are drawn with '#' and '.' as seen from the side, with a blank
+// line between each one
+fn parse_rock_shapes(file_name: &str) -> std::io::Result<Vec<RockShape>> {
+    let input = fs::read_to_string(file_name)?;
+    let mut shapes = Vec::new();
+    let mut rows: Vec<&str> = Vec::new();
+
+    for (i, line) in input.lines().map(str::trim_end).chain([""]).enumerate() {
+        if !line.is_empty() {
+            if let Some(ch) = line.chars().find(|ch| *ch != '#' && *ch != '.') {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("unexpected '{}' in rock on line {}", ch, i + 1)
+                ));
+            }
+
+            rows.push(line);
+            continue;
+        }
+
+        if rows.is_empty() {
+            continue;
+        }
+
+        // Bottom row first
+        let cells: Vec<(u64, u64)> = rows.iter()
+            .rev()
+            .enumerate()
+            .flat_map(|(y, row)| row.chars()
+                .enumerate()
+                .filter(|(_, ch)| *ch == '#')
+                .map(move |(x, _)| (x as u64, y as u64)))
+            .collect();
+
+        rows.clear();
+
+        if cells.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("rock ending on line {} has no '#'", i)
+            ));
+        }
+
+        let min_x = cells.iter().map(|c| c.0).min().unwrap();
+        let min_y = cells.iter().map(|c| c.1).min().unwrap();
+        let cells: Vec<(u64, u64)> = cells.iter().map(|(x, y)| (x - min_x, y - min_y)).collect();
+        let width = cells.iter().map(|c| c.0).max().unwrap() + 1;
+
+        shapes.push(RockShape { cells, width });
+    }
+
+    if shapes.is_empty() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData, "no rock shapes"
+        ));
+    }
+
+    Ok(shapes)
+}
@@ -1,12 +1,10 @@
-use std::cmp;
 use std::fs;
-//use std::fmt;
+use std::fmt;
 use std::io::BufReader;
 use std::io::prelude::*;
 use std::iter::Peekable;
-use std::collections::HashMap;
 
-use crate::days::day_18::Entity::*;
+use crate::days::day_18::Voxel::*;
 
 
 const CUBE_OFFSETS: [(i8,i8,i8); 6] = [
@@ -19,201 +17,227 @@ const CUBE_OFFSETS: [(i8,i8,i8); 6] = [
 ];
 
 
+// Voxels of the droplet's bounding box plus a layer of air all round, so
+// all the air outside the droplet is connected
 struct Space {
-    entities: Vec<Entity>,
+    voxels: Vec<Voxel>,
+    // Position of the first voxel
+    origin: (i64, i64, i64),
     width: usize,
     height: usize,
     length: usize,
 }
 
-#[derive(Clone, Copy)]
-enum Entity {
-    Air(bool),
-    CubeFace(u32, bool),
-    Cube,
+#[derive(Clone, Copy, PartialEq)]
+enum Voxel {
+    Lava,
+    // Air that can't reach the outside
+    Air,
+    Outside,
+}
+
+// Group of connected voxels of one kind
+struct Region {
+    size: usize,
+    min: (i64, i64, i64),
+    max: (i64, i64, i64),
 }
 
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
     let tuples = parse_tuples(file_name)?;
 
-    part_one(tuples.clone());
-    part_two(tuples.clone());
+    if tuples.is_empty() {
+        println!("no lava");
+        return Ok(());
+    }
 
-    Ok(())
-}
+    let mut space = Space::new(&tuples);
+    space.fill_outside();
 
-/* Part Two */
-fn part_two(tuples: Vec<(u32,u32,u32)>) {
-    let mut space = Space::new();
+    println!("p1: {}", space.surface_area(|voxel| voxel != Lava));
+    println!("p2: {}", space.surface_area(|voxel| voxel == Outside));
 
-    space.add_cubes(&tuples);
-    space.init_outside();
+    let (min, max) = space.bounding_box();
+    println!("bounding box: {},{},{} to {},{},{}", min.0, min.1, min.2, max.0, max.1, max.2);
 
-    let mut score = 0;
+    let components = space.regions(Lava);
+    println!("lava components: {}", components.len());
+    for component in &components {
+        println!("  {}", component);
+    }
 
-    for entity in space.entities {
-        if let CubeFace(cons, true) = entity {
-            score += cons;
-        }
+    let pockets = space.regions(Air);
+    println!("air pockets: {} ({} cubes)",
+        pockets.len(), pockets.iter().map(|p| p.size).sum::<usize>());
+    for pocket in &pockets {
+        println!("  {}", pocket);
     }
 
-    println!("p2: {}", score);
+    Ok(())
 }
 
-impl Space {
-    fn new() -> Space {
-        Space {
-            entities: Vec::new(),
-            width: 0,
-            height: 0,
-            length: 0,
-        }
-    }
 
-    fn add_cubes(&mut self, tuples: &Vec<(u32,u32,u32)>) {
-        let entities_map = self.init_intities(tuples);
-        
-        self.entities = vec![Air(false); self.width * self.height * self.length];
+impl Space {
+    fn new(tuples: &[(u32, u32, u32)]) -> Space {
+        let cubes: Vec<(i64, i64, i64)> = tuples.iter()
+            .map(|(x, y, z)| (*x as i64, *y as i64, *z as i64))
+            .collect();
+
+        let min = |axis: fn(&(i64, i64, i64)) -> i64| cubes.iter().map(axis).min().unwrap() - 1;
+        let max = |axis: fn(&(i64, i64, i64)) -> i64| cubes.iter().map(axis).max().unwrap() + 1;
+
+        let origin = (min(|c| c.0), min(|c| c.1), min(|c| c.2));
+
+        let mut space = Space {
+            voxels: Vec::new(),
+            origin,
+            width: (max(|c| c.0) - origin.0 + 1) as usize,
+            height: (max(|c| c.1) - origin.1 + 1) as usize,
+            length: (max(|c| c.2) - origin.2 + 1) as usize,
+        };
 
-        for ((x, y, z), entity) in entities_map {
-            let index = self.get_index(x, y, z);
+        space.voxels = vec![Air; space.width * space.height * space.length];
 
-            self.entities[index] = entity;
+        for cube in cubes {
+            let index = space.get_index(cube);
+            space.voxels[index] = Lava;
         }
-    }
-
-
-    fn init_outside(&mut self) {
-        self.entities[0] = Air(true);
 
-        let mut visisted = vec![false; self.entities.len()];
-
-        self.dfs(0, 0, 0, &mut visisted);
+        space
     }
 
+    // Every voxel the outside air can reach is outside. The padding means
+    // the first voxel is always outside
+    fn fill_outside(&mut self) {
+        let mut visited = vec![false; self.voxels.len()];
 
-    fn dfs(&mut self, x: u32, y: u32, z: u32, visisted: &mut Vec<bool>) {
-        let index = self.get_index(x, y, z);
-
-        if visisted[index] {
-            return;
+        for index in self.flood(0, &mut visited) {
+            self.voxels[index] = Outside;
         }
+    }
 
-        visisted[index] = true;
+    // Lava faces touching a voxel that matches
+    fn surface_area<F: Fn(Voxel) -> bool>(&self, counts: F) -> usize {
+        (0..self.voxels.len())
+            .filter(|index| self.voxels[*index] == Lava)
+            .flat_map(|index| self.neighbours(index))
+            .filter(|neighbour| counts(self.voxels[*neighbour]))
+            .count()
+    }
 
-        if let Cube = self.entities[index] {
-            return;
-        }
+    // Smallest and largest corners of the lava
+    fn bounding_box(&self) -> ((i64, i64, i64), (i64, i64, i64)) {
+        let far = self.get_position(self.voxels.len() - 1);
 
-        self.entities[index] = match self.entities[index] {
-            Air(false) => Air(true),
-            CubeFace(cons, false) => CubeFace(cons, true),
-            other => other
-        };
+        (
+            (self.origin.0 + 1, self.origin.1 + 1, self.origin.2 + 1),
+            (far.0 - 1, far.1 - 1, far.2 - 1),
+        )
+    }
 
-        for (x_offset, y_offset, z_offset) in CUBE_OFFSETS {
-            let x = x as i32 + x_offset as i32;
-            let y = y as i32 + y_offset as i32;
-            let z = z as i32 + z_offset as i32;
+    // Connected groups of one kind of voxel, largest first
+    fn regions(&self, kind: Voxel) -> Vec<Region> {
+        let mut seen = vec![false; self.voxels.len()];
+        let mut regions = Vec::new();
 
-            if x < 0 || y < 0 || z < 0 || x >= self.width as i32 || y >= self.height as i32 || z >= self.length as i32 {
+        for start in 0..self.voxels.len() {
+            if seen[start] || self.voxels[start] != kind {
                 continue;
             }
 
-            self.dfs(x as u32, y as u32, z as u32, visisted);
-        }
-    }
+            let region = self.flood(start, &mut seen);
+            let positions: Vec<(i64, i64, i64)> = region.iter()
+                .map(|index| self.get_position(*index))
+                .collect();
 
+            regions.push(Region {
+                size: positions.len(),
+                min: positions.iter().fold(positions[0], |m, p| (m.0.min(p.0), m.1.min(p.1), m.2.min(p.2))),
+                max: positions.iter().fold(positions[0], |m, p| (m.0.max(p.0), m.1.max(p.1), m.2.max(p.2))),
+            });
+        }
 
-    fn get_index(&self, x: u32, y: u32, z: u32) -> usize {
-        (z as usize * self.width * self.height) + (y as usize * self.width) + x as usize
+        regions.sort_by_key(|region| std::cmp::Reverse(region.size));
+        regions
     }
 
+    // Every voxel connected to start that is the same kind as it and not
+    // visited yet. Uses its own stack so large droplets can't overflow the
+    // call stack
+    fn flood(&self, start: usize, visited: &mut [bool]) -> Vec<usize> {
+        let kind = self.voxels[start];
+        let mut stack = vec![start];
+        let mut output = Vec::new();
 
-    // fn get_position(&self, index: usize) -> (u32, u32, u32) {
-    //     let z = index / (self.width * self.height);
-    //     let index = index - (z * (self.width * self.height));
-    //     let y = index / self.width;
-    //     let x = index % self.width;
+        visited[start] = true;
 
-    //     (x as u32, y as u32, z as u32)
-    // }
+        while let Some(index) = stack.pop() {
+            output.push(index);
 
-
-    fn init_intities(&mut self, tuples: &Vec<(u32,u32,u32)>) -> HashMap<(u32, u32, u32), Entity> {
-        let mut entities = HashMap::new();
-
-        for (x, y, z) in tuples {
-            self.add_cube_to_map(&mut entities, *x, *y, *z);
+            for neighbour in self.neighbours(index) {
+                if !visited[neighbour] && self.voxels[neighbour] == kind {
+                    visited[neighbour] = true;
+                    stack.push(neighbour);
+                }
+            }
         }
 
-        entities
+        output
     }
 
+    fn neighbours(&self, index: usize) -> impl Iterator<Item = usize> + '_ {
+        let (x, y, z) = self.get_local(index);
 
-    fn add_cube_to_map(&mut self, entities: &mut HashMap<(u32, u32, u32), Entity>, x: u32, y: u32, z: u32) {
-        entities.insert((x, y, z), Cube);
+        CUBE_OFFSETS.iter().filter_map(move |(x_offset, y_offset, z_offset)| {
+            let x = x.checked_add_signed(*x_offset as isize)?;
+            let y = y.checked_add_signed(*y_offset as isize)?;
+            let z = z.checked_add_signed(*z_offset as isize)?;
+
+            if x >= self.width || y >= self.height || z >= self.length {
+                return None;
+            }
 
-        for (x_offset, y_offset, z_offset) in CUBE_OFFSETS {
-            let x = (x as i32 + x_offset as i32) as u32;
-            let y = (y as i32 + y_offset as i32) as u32;
-            let z = (z as i32 + z_offset as i32) as u32;
+            Some((z * self.width * self.height) + (y * self.width) + x)
+        })
+    }
 
-            self.width = cmp::max(self.width, (x + 1).try_into().unwrap());
-            self.height = cmp::max(self.height, (y + 1).try_into().unwrap());
-            self.length = cmp::max(self.length, (z + 1).try_into().unwrap());
 
-            let pos = (x, y, z);
+    fn get_index(&self, pos: (i64, i64, i64)) -> usize {
+        let x = (pos.0 - self.origin.0) as usize;
+        let y = (pos.1 - self.origin.1) as usize;
+        let z = (pos.2 - self.origin.2) as usize;
 
-            match entities.get(&pos) {
-                None => { 
-                    entities.insert(pos, CubeFace(1, false)); 
-                },
-                Some(CubeFace(connections, is_outside)) => {
-                    entities.insert(pos, CubeFace(
-                        connections + 1, *is_outside
-                    ));
-                },
-                _ => (),
-            };
-        }
+        (z * self.width * self.height) + (y * self.width) + x
     }
-}
 
 
+    fn get_local(&self, index: usize) -> (usize, usize, usize) {
+        let z = index / (self.width * self.height);
+        let index = index - (z * (self.width * self.height));
+        let y = index / self.width;
+        let x = index % self.width;
 
-/* Part One */
-fn part_one(tuples: Vec<(u32,u32,u32)>) {
-    let mut entities = HashMap::new();
-
-    for (x,y,z) in tuples {
-        entities.insert((x,y,z), Cube);
+        (x, y, z)
+    }
 
-        for (x_offset, y_offset, z_offset) in CUBE_OFFSETS {
-            let x = (x as i32 + x_offset as i32) as u32;
-            let y = (y as i32 + y_offset as i32) as u32;
-            let z = (z as i32 + z_offset as i32) as u32;
 
-            let pos = (x, y, z);
+    fn get_position(&self, index: usize) -> (i64, i64, i64) {
+        let (x, y, z) = self.get_local(index);
 
-            if !entities.contains_key(&pos) {
-                entities.insert(pos, CubeFace(1, true));
-            } else if let Some(CubeFace(connected_cubes, _)) = entities.get(&pos) {
-                entities.insert(pos, CubeFace(connected_cubes + 1, true));
-            }
-        }
+        (self.origin.0 + x as i64, self.origin.1 + y as i64, self.origin.2 + z as i64)
     }
+}
 
-    let mut score = 0;
 
-    for (_, v) in entities {
-        if let CubeFace(count, _) = v {
-            score += count;
-        }
+impl fmt::Display for Region {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{} cubes from {},{},{} to {},{},{}",
+            self.size,
+            self.min.0, self.min.1, self.min.2,
+            self.max.0, self.max.1, self.max.2
+        )
     }
-
-    println!("p1: {}", score);
 }
 
 
@@ -1,54 +1,110 @@
 use std::fs::File;
-use std::io::BufReader;
 use std::io::prelude::*;
 
-use std::collections::VecDeque;
-use std::collections::HashSet;
+use crate::days::arg_or;
+
+
+// Finds the end of every window of window_size distinct symbols in a
+// single pass, keeping a count of each symbol in the current window
+pub struct MarkerDetector<'a> {
+    data: &'a [u8],
+    window_size: usize,
+    counts: [u32; 256],
+    duplicates: usize,
+    position: usize,
+}
+
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
-    let file = File::open(file_name)
-        .expect("File not found");
-    let reader = BufReader::new(file);
-    let line = reader.lines().next().unwrap()?;
-
-    let mut packet = VecDeque::new();
-
-    let start_of_packet_size = 4;
-    let start_of_message_size = 14;
-    let is_part_one = false;
-
-    let (skip_amount, offset) = if is_part_one {
-        (start_of_packet_size - 1, start_of_packet_size)
-    } else {
-        (start_of_message_size - 1, start_of_message_size)
-    };
-
-    line.chars()    
-        .into_iter()
-        .take(skip_amount)
-        .for_each(|x| packet.push_front(x as u32 - 'a' as u32));
-
-    'outer: for (i, ch) in line.chars().skip(skip_amount).enumerate() {
-        packet.push_front(ch as u32 - 'a' as u32);
-
-        // Check for duplicates
-        let mut set = HashSet::new();
-
-        for v in &packet {
-            if set.contains(v) {
-                // Contains duplicates drop last element and continue
-                packet.pop_back();
-                continue 'outer;
-            }
-            set.insert(v);
-        }
+    let mut data = Vec::new();
+    File::open(file_name)
+        .expect("File not found")
+        .read_to_end(&mut data)?;
 
-        // Set contains no duplicates
-        println!("{}", i + offset);
-        break;
-        
+    while let Some(b'\n' | b'\r') = data.last() {
+        data.pop();
     }
 
+    // Usage: cargo run -- 6 [window size] [all]
+    let window_size = arg_or(2, 14);
+    let find_all = arg_or(3, String::new()) == "all";
+
+    match first_marker(&data, window_size) {
+        Some(pos) => println!("first marker: {}", pos),
+        None => println!("no marker of size {}", window_size),
+    }
+
+    if find_all {
+        let markers = all_markers(&data, window_size);
+        println!("all markers ({}): {:?}", markers.len(), markers);
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+
+pub fn first_marker(data: &[u8], window_size: usize) -> Option<usize> {
+    MarkerDetector::new(data, window_size).next()
+}
+
+
+pub fn all_markers(data: &[u8], window_size: usize) -> Vec<usize> {
+    MarkerDetector::new(data, window_size).collect()
+}
+
+
+impl<'a> MarkerDetector<'a> {
+    pub fn new(data: &'a [u8], window_size: usize) -> MarkerDetector<'a> {
+        MarkerDetector {
+            data,
+            window_size,
+            counts: [0; 256],
+            duplicates: 0,
+            position: 0,
+        }
+    }
+
+    fn push(&mut self, symbol: u8) {
+        let count = &mut self.counts[symbol as usize];
+        *count += 1;
+        if *count == 2 {
+            self.duplicates += 1;
+        }
+    }
+
+    fn pop(&mut self, symbol: u8) {
+        let count = &mut self.counts[symbol as usize];
+        *count -= 1;
+        if *count == 1 {
+            self.duplicates -= 1;
+        }
+    }
+}
+
+
+impl Iterator for MarkerDetector<'_> {
+    // Number of symbols processed when the marker completes
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.window_size == 0 {
+            return None;
+        }
+
+        while self.position < self.data.len() {
+            self.push(self.data[self.position]);
+
+            if self.position >= self.window_size {
+                self.pop(self.data[self.position - self.window_size]);
+            }
+
+            self.position += 1;
+
+            if self.position >= self.window_size && self.duplicates == 0 {
+                return Some(self.position);
+            }
+        }
+
+        None
+    }
+}
@@ -4,13 +4,15 @@ use std::io::prelude::*;
 
 use crate::d7::TerminalLine::*;
 use crate::d7::CDArgs::*;
-use std::collections::HashMap;
+use crate::days::arg_or;
+use std::collections::BTreeMap;
+use std::fmt;
 
 enum TerminalLine {
     CD(CDArgs),
     LS,
     FS(File),
-    DR(Dir),
+    DR(String),
 }
 
 enum CDArgs {
@@ -21,165 +23,446 @@ enum CDArgs {
 
 struct File {
     name: String,
-    size: u32,
+    size: u64,
 }
 
 struct Dir {
     name: String,
-    files: HashMap<String, File>,
-    dirs: HashMap<String, u32>,
-    parent_id: u32,
-    size: u32
+    files: BTreeMap<String, u64>,
+    dirs: BTreeMap<String, usize>,
+    parent_id: usize,
 }
 
+// Directory tree rebuilt from a terminal transcript. Directories are
+// stored in creation order so a parent always comes before its children
+pub struct FileSystem {
+    dirs: Vec<Dir>,
+}
+
+pub struct Entry {
+    pub path: String,
+    pub size: u64,
+    pub is_dir: bool,
+}
+
+// Inconsistency found while replaying a transcript
+enum Issue {
+    Unparsable(String),
+    FileResized { name: String, old: u64, new: u64 },
+    CdAboveRoot,
+    CdUnlisted(String),
+    EntryWithoutLs(String),
+}
+
+const ROOT_ID: usize = 0;
+const DISK_SIZE: u64 = 70000000;
+const UPDATE_SIZE: u64 = 30000000;
+
 
 pub fn run(file_name: &str) -> std::io::Result<()> {
     let file = fs::File::open(file_name)
         .expect("File not found");
     let reader = BufReader::new(file);
 
-    let mut dirs: HashMap<u32, Dir> = HashMap::new();
-    let mut max_id = 0;
+    /* Build file system */
+    let lines = reader.lines().collect::<std::io::Result<Vec<String>>>()?;
+    let (fs, issues) = FileSystem::replay(&lines);
+
+    // Usage: cargo run -- 7 [tree [PATH] | du | find-size MIN [MAX] |
+    //                        find-name GLOB | candidates [NEEDED] |
+    //                        validate | canonical]
+    match arg_or(2, String::new()).as_str() {
+        "validate" => {
+            for (line_no, issue) in &issues {
+                println!("line {}: {}", line_no, issue);
+            }
+            println!("{} issue(s) found", issues.len());
+        },
+        "canonical" => {
+            for command in fs.canonical_transcript() {
+                println!("{}", command);
+            }
+        },
+        "tree" => {
+            let path = arg_or(3, String::from("/"));
+            match fs.lookup(&path) {
+                Some(dir_id) => print!("{}", fs.tree(dir_id)),
+                None => println!("no such directory: {}", path),
+            }
+        },
+        "du" => {
+            for entry in fs.du() {
+                println!("{}\t{}", entry.size, entry.path);
+            }
+        },
+        "find-size" => {
+            let min = arg_or(3, 0);
+            let max = arg_or(4, u64::MAX);
+            print_entries(&fs.find_by_size(min, max));
+        },
+        "find-name" => {
+            let pattern = arg_or(3, String::from("*"));
+            print_entries(&fs.find_by_name(&pattern));
+        },
+        "candidates" => {
+            let needed = arg_or(3, UPDATE_SIZE);
+            for entry in fs.deletion_candidates(DISK_SIZE, needed) {
+                println!("{}\t{}", entry.size, entry.path);
+            }
+        },
+        _ => {
+            let used = fs.size_of(ROOT_ID);
+            println!("unused: {}", DISK_SIZE - used);
+            println!("part one: {}", part_one(&fs));
+            println!("part two: {}", part_two(&fs));
+        },
+    }
 
-    // Add root dir 
-    dirs.insert(0, Dir {
-        name: "\\".to_string(),
-        files: HashMap::new(),
-        dirs: HashMap::new(),
-        parent_id: 0,
-        size: 0
-    });
+    Ok(())
+}
 
-    let mut curr_dir_id = 0;
+/* Solution */
+fn part_one(fs: &FileSystem) -> u64 {
+    fs.find_by_size(0, 100000)
+        .iter()
+        .filter(|e| e.is_dir)
+        .map(|e| e.size)
+        .sum()
+}
 
-    /* Build file system */
-    for l in reader.lines().skip(1) { /* Can skip first as is always cd / */
-        let command = parse_terminal_line(&l?).unwrap();
-        command.print();
-        
+fn part_two(fs: &FileSystem) -> u64 {
+    fs.deletion_candidates(DISK_SIZE, UPDATE_SIZE)
+        .first()
+        .map(|e| e.size)
+        .unwrap_or(0)
+}
+
+fn print_entries(entries: &[Entry]) {
+    for entry in entries {
+        let kind = if entry.is_dir { "dir" } else { "file" };
+        println!("{}\t{}\t{}", entry.size, kind, entry.path);
+    }
+}
+
+
+
+/* FS Building */
+impl FileSystem {
+    fn new() -> FileSystem {
+        FileSystem {
+            dirs: vec![Dir::new("/".to_string(), ROOT_ID)],
+        }
+    }
+
+    // Rebuilds the tree from a transcript, collecting every
+    // inconsistency along with its (1 based) line number
+    fn replay(lines: &[String]) -> (FileSystem, Vec<(usize, Issue)>) {
+        let mut fs = FileSystem::new();
+        let mut issues = Vec::new();
+        let mut curr_dir_id = ROOT_ID;
+        let mut in_listing = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            let command = match parse_terminal_line(line) {
+                Some(command) => command,
+                None => {
+                    issues.push((i + 1, Issue::Unparsable(line.clone())));
+                    continue;
+                }
+            };
+
+            match &command {
+                LS => in_listing = true,
+                CD(_) => in_listing = false,
+                FS(File { name, .. }) | DR(name) if !in_listing =>
+                    issues.push((i + 1, Issue::EntryWithoutLs(name.clone()))),
+                _ => (),
+            }
+
+            let (new_dir_id, issue) = fs.execute(command, curr_dir_id);
+            curr_dir_id = new_dir_id;
+
+            if let Some(issue) = issue {
+                issues.push((i + 1, issue));
+            }
+        }
+
+        (fs, issues)
+    }
+
+    // Applies one transcript line, returning the new working directory.
+    // Listing a directory twice merges the listings: sizes are updated,
+    // but entries missing from the later listing stay in the tree.
+    // Changing into a directory that was never listed creates it
+    fn execute(
+        &mut self, command: TerminalLine, curr_dir_id: usize
+    ) -> (usize, Option<Issue>) {
         match command {
-            LS => () /* Nothing needed to be done */ ,
-            CD(arg) => curr_dir_id = get_new_curr_dir(arg, curr_dir_id, &dirs), 
-            FS(file) => add_file(dirs.get_mut(&curr_dir_id).unwrap(), file),
-            DR(mut dir) => {
-                max_id = max_id + 1;
-                dir.parent_id = curr_dir_id;
-                dirs.get_mut(&curr_dir_id).unwrap().dirs.insert(
-                    dir.name.clone(), max_id
-                );
-                dirs.insert(max_id, dir);
+            LS => (curr_dir_id, None),
+            CD(GoRoot) => (ROOT_ID, None),
+            CD(GoBack) if curr_dir_id == ROOT_ID => (ROOT_ID, Some(Issue::CdAboveRoot)),
+            CD(GoBack) => (self.dirs[curr_dir_id].parent_id, None),
+            CD(GoFile(name)) => {
+                let issue = if self.dirs[curr_dir_id].dirs.contains_key(&name) {
+                    None
+                } else {
+                    Some(Issue::CdUnlisted(name.clone()))
+                };
+                (self.add_dir(curr_dir_id, name), issue)
             },
-        };
+            FS(file) => {
+                let files = &mut self.dirs[curr_dir_id].files;
+                let issue = match files.insert(file.name.clone(), file.size) {
+                    Some(old) if old != file.size => Some(Issue::FileResized {
+                        name: file.name, old, new: file.size
+                    }),
+                    _ => None,
+                };
+                (curr_dir_id, issue)
+            },
+            DR(name) => {
+                self.add_dir(curr_dir_id, name);
+                (curr_dir_id, None)
+            },
+        }
     }
 
-    /* Calculate the fs sizes */
-    let unused_space = 70000000 - populate_fs_size(&mut dirs, 0);
-    let space_needed = 30000000 - unused_space;
-    print_fs(&dirs, 0, 0);
+    fn add_dir(&mut self, parent_id: usize, name: String) -> usize {
+        if let Some(id) = self.dirs[parent_id].dirs.get(&name) {
+            return *id;
+        }
 
-    println!("unsued: {}", unused_space);
-    println!("part one: {}", part_one(&dirs, 0));
-    println!("part two: {}", part_two(&dirs, space_needed));
+        let id = self.dirs.len();
+        self.dirs[parent_id].dirs.insert(name.clone(), id);
+        self.dirs.push(Dir::new(name, parent_id));
+        id
+    }
+}
 
-    Ok(())
+impl Dir {
+    fn new(name: String, parent_id: usize) -> Dir {
+        Dir {
+            name,
+            files: BTreeMap::new(),
+            dirs: BTreeMap::new(),
+            parent_id,
+        }
+    }
 }
 
-/* Solution */
-fn print_fs(dirs: &HashMap<u32, Dir>, curr_dir: u32, curr_depth: u32) {
-    // Print current
-    let dir = dirs.get(&curr_dir).unwrap();
 
-    for _ in 0..curr_depth {print!("  "); }
-    println!("- {} (dir, size={})", dir.name, dir.size);
+/* Queries */
+impl FileSystem {
+    pub fn path(&self, dir_id: usize) -> String {
+        if dir_id == ROOT_ID {
+            return "/".to_string();
+        }
 
-    // Print dirs
-    for (_, dir_id) in &dirs.get(&curr_dir).unwrap().dirs {
-        print_fs(&dirs, *dir_id, curr_depth + 1);
-    }
+        let mut names = Vec::new();
+        let mut curr = dir_id;
 
-    // Print files 
-    for (_, file) in &dirs.get(&curr_dir).unwrap().files {
-        for _ in 0..(curr_depth + 1) {print!("  "); }
-        println!("- {} (file, size={})", file.name, file.size);
+        while curr != ROOT_ID {
+            names.push(self.dirs[curr].name.as_str());
+            curr = self.dirs[curr].parent_id;
+        }
+
+        names.reverse();
+        format!("/{}", names.join("/"))
     }
-}
 
-fn populate_fs_size(dirs: &mut HashMap<u32, Dir>, curr_dir: u32) -> u32 {
-    let mut size = 0;
+    pub fn lookup(&self, path: &str) -> Option<usize> {
+        let mut curr = ROOT_ID;
 
-    // get size of all files 
-    for (_, file) in &dirs.get(&curr_dir).unwrap().files {
-        size += file.size;
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            curr = *self.dirs[curr].dirs.get(name)?;
+        }
+
+        Some(curr)
     }
 
-    // Get all sub dirs 
-    let mut sub_dirs = Vec::new();
+    // Recursive size of every directory. Children are always created
+    // after their parent so a single reverse pass is enough
+    fn dir_sizes(&self) -> Vec<u64> {
+        let mut sizes: Vec<u64> = self.dirs.iter()
+            .map(|d| d.files.values().sum())
+            .collect();
+
+        for id in (1..self.dirs.len()).rev() {
+            sizes[self.dirs[id].parent_id] += sizes[id];
+        }
 
-    for (_, id) in &dirs.get(&curr_dir).unwrap().dirs {
-        sub_dirs.push(*id);
+        sizes
     }
 
-    for k in sub_dirs {
-        size += populate_fs_size(dirs, k);
+    pub fn size_of(&self, dir_id: usize) -> u64 {
+        self.dir_sizes()[dir_id]
     }
 
-    // Save this size 
-    dirs.get_mut(&curr_dir).unwrap().size = size;
-    size
-}
+    pub fn du(&self) -> Vec<Entry> {
+        let sizes = self.dir_sizes();
 
-fn part_one(dirs: &HashMap<u32, Dir>, curr_dir: u32) -> u32 {
-    let mut sum = 0;
+        (0..self.dirs.len())
+            .map(|id| Entry { path: self.path(id), size: sizes[id], is_dir: true })
+            .collect()
+    }
 
-    if dirs.get(&curr_dir).unwrap().size <= 100000 {
-        sum += dirs.get(&curr_dir).unwrap().size;
+    pub fn find_by_size(&self, min: u64, max: u64) -> Vec<Entry> {
+        self.find(|_, size| min <= size && size <= max)
     }
 
-    for (_, id) in &dirs.get(&curr_dir).unwrap().dirs {
-        sum += part_one(dirs, *id);
+    pub fn find_by_name(&self, pattern: &str) -> Vec<Entry> {
+        self.find(|name, _| glob_match(pattern, name))
     }
 
+    fn find<F: Fn(&str, u64) -> bool>(&self, predicate: F) -> Vec<Entry> {
+        let sizes = self.dir_sizes();
+        let mut output = Vec::new();
 
-    sum 
-}
+        for (id, dir) in self.dirs.iter().enumerate() {
+            let path = self.path(id);
 
-fn part_two(dirs: &HashMap<u32, Dir>, space_needed: u32) -> u32 {
-    let mut curr_best = dirs.get(&0).unwrap();
+            if predicate(&dir.name, sizes[id]) {
+                output.push(Entry { path: path.clone(), size: sizes[id], is_dir: true });
+            }
 
-    for (_, dir) in dirs {
-        if dir.size < space_needed {
-            continue;
+            for (name, size) in &dir.files {
+                if predicate(name, *size) {
+                    output.push(Entry {
+                        path: join_path(&path, name), size: *size, is_dir: false
+                    });
+                }
+            }
         }
 
-        if dir.size < curr_best.size {
-            curr_best = dir;
+        output
+    }
+
+    // Directories that free at least needed space when deleted,
+    // smallest first
+    pub fn deletion_candidates(&self, disk_size: u64, needed: u64) -> Vec<Entry> {
+        let sizes = self.dir_sizes();
+        let unused = disk_size.saturating_sub(sizes[ROOT_ID]);
+
+        if unused >= needed {
+            return Vec::new();
         }
+
+        let to_free = needed - unused;
+        let mut output: Vec<Entry> = self.du()
+            .into_iter()
+            .filter(|e| e.size >= to_free)
+            .collect();
+
+        output.sort_by_key(|e| e.size);
+        output
     }
 
-    curr_best.size
+    pub fn tree(&self, dir_id: usize) -> String {
+        let sizes = self.dir_sizes();
+        let mut output = String::new();
+        self.write_tree(&mut output, &sizes, dir_id, 0);
+        output
+    }
+
+    fn write_tree(&self, output: &mut String, sizes: &[u64], dir_id: usize, depth: usize) {
+        let dir = &self.dirs[dir_id];
+
+        output.push_str(&"  ".repeat(depth));
+        output.push_str(&format!("- {} (dir, size={})\n", dir.name, sizes[dir_id]));
+
+        for id in dir.dirs.values() {
+            self.write_tree(output, sizes, *id, depth + 1);
+        }
+
+        for (name, size) in &dir.files {
+            output.push_str(&"  ".repeat(depth + 1));
+            output.push_str(&format!("- {} (file, size={})\n", name, size));
+        }
+    }
 }
 
+/* Transcript Output */
+impl FileSystem {
+    // Shortest transcript that rebuilds this tree: empty directories are
+    // never entered and the final walk back up to the root is dropped
+    fn canonical_transcript(&self) -> Vec<TerminalLine> {
+        let mut output = vec![CD(GoRoot)];
+        self.write_transcript(&mut output, ROOT_ID);
+
+        while let Some(CD(GoBack)) = output.last() {
+            output.pop();
+        }
+
+        output
+    }
+
+    fn write_transcript(&self, output: &mut Vec<TerminalLine>, dir_id: usize) {
+        let dir = &self.dirs[dir_id];
+
+        if dir.dirs.is_empty() && dir.files.is_empty() {
+            return;
+        }
+
+        output.push(LS);
+
+        for name in dir.dirs.keys() {
+            output.push(DR(name.clone()));
+        }
+
+        for (name, size) in &dir.files {
+            output.push(FS(File { name: name.clone(), size: *size }));
+        }
+
+        for (name, id) in &dir.dirs {
+            let child = &self.dirs[*id];
+            if child.dirs.is_empty() && child.files.is_empty() {
+                continue;
+            }
 
+            output.push(CD(GoFile(name.clone())));
+            self.write_transcript(output, *id);
+            output.push(CD(GoBack));
+        }
+    }
+}
 
-/* FS Buildnig */
-fn add_file(curr_dir: &mut Dir, file: File) {
-    curr_dir.files.insert(file.name.clone(), file); 
+fn join_path(dir_path: &str, name: &str) -> String {
+    if dir_path == "/" {
+        format!("/{}", name)
+    } else {
+        format!("{}/{}", dir_path, name)
+    }
 }
 
-fn get_new_curr_dir(
-        cd_arg: CDArgs, curr_dir_id: u32, 
-        dirs: &HashMap<u32, Dir>
-) -> u32 {
-    match cd_arg {
-        GoRoot => 
-            0,
-        GoBack => 
-            dirs.get(&curr_dir_id).unwrap().parent_id,
-        GoFile(name) => 
-            dirs.get(&curr_dir_id).unwrap().dirs.get(&name).unwrap().clone()       
+// Shell style glob supporting '*' and '?'
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = last_star {
+            // Let the last star consume one more character
+            p = star_p + 1;
+            n = star_n + 1;
+            last_star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
     }
+
+    pattern[p..].iter().all(|ch| *ch == '*')
 }
 
+
 /* Parsing */
 fn parse_terminal_line(line: &str) -> Option<TerminalLine> {
     match line.chars().next() {
@@ -191,39 +474,18 @@ fn parse_terminal_line(line: &str) -> Option<TerminalLine> {
 }
 
 
-fn parse_terminal_file(line: &str) -> Option<TerminalLine> { 
-    let mut size = 0;
-    let mut name = String::new();
-
-    for ch in line.chars() {
-        match ch {
-            '0'..='9' => size = (size * 10) + (ch as u32 - '0' as u32),
-            ' ' => (), 
-            _ => name.push(ch),
-        }
-    }
+fn parse_terminal_file(line: &str) -> Option<TerminalLine> {
+    let (size, name) = line.split_once(' ')?;
 
     Some(FS(File {
-        size: size,
-        name: name
+        size: size.parse().ok()?,
+        name: name.to_string(),
     }))
 }
 
 
 fn parse_terminal_dir(line: &str) -> Option<TerminalLine> {
-    let mut name = String::new();
-
-    for ch in line.chars().skip(4) {
-        name.push(ch);
-    }
-
-    Some(DR(Dir {
-        name: name,
-        files: HashMap::new(),
-        dirs: HashMap::new(),
-        parent_id: 0,
-        size: 0
-    }))
+    Some(DR(line.strip_prefix("dir ")?.to_string()))
 }
 
 
@@ -234,37 +496,40 @@ fn parse_terminal_command(line: &str) -> Option<TerminalLine> {
         _ => return None
     };
 
-    match line.chars().nth(5) {
-        Some('.') => return Some(CD(GoBack)),
-        Some('/') => return Some(CD(GoRoot)),
-        _ => ()
-    };
-
-    let mut name = String::new();
-
-    for ch in line.chars().skip(5) {
-        name.push(ch);
+    match line.get(5..)? {
+        ".." => Some(CD(GoBack)),
+        "/" => Some(CD(GoRoot)),
+        name => Some(CD(GoFile(name.to_string()))),
     }
-
-    Some(CD(GoFile(name)))
 }
 
 
-impl TerminalLine {
-    fn print(self: &TerminalLine) {
+impl fmt::Display for TerminalLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            CD(arg) => print_cd(arg),
-            LS => println!("$ ls"),
-            FS(file) => println!("{} {}", file.size, file.name),
-            DR(dir) => println!("dir {}", dir.name),
+            CD(GoRoot) => write!(f, "$ cd /"),
+            CD(GoBack) => write!(f, "$ cd .."),
+            CD(GoFile(name)) => write!(f, "$ cd {}", name),
+            LS => write!(f, "$ ls"),
+            FS(file) => write!(f, "{} {}", file.size, file.name),
+            DR(name) => write!(f, "dir {}", name),
         }
     }
 }
 
-fn print_cd(arg: &CDArgs) {
-    match arg {
-        GoRoot => println!("$ cd /"),
-        GoFile(name) => println!("$ cd {}", name),
-        GoBack => println!("$ cd .."),
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Issue::Unparsable(line) =>
+                write!(f, "could not parse '{}'", line),
+            Issue::FileResized { name, old, new } =>
+                write!(f, "file {} listed with size {} but was {}", name, new, old),
+            Issue::CdAboveRoot =>
+                write!(f, "cd .. at root"),
+            Issue::CdUnlisted(name) =>
+                write!(f, "cd into {} which was never listed", name),
+            Issue::EntryWithoutLs(name) =>
+                write!(f, "entry {} listed outside of ls output", name),
+        }
     }
-}
\ No newline at end of file
+}
@@ -2,186 +2,220 @@ use std::fs;
 use std::io::BufReader;
 use std::io::prelude::*;
 
-pub fn run(file_name: &str) -> std::io::Result<()> {
-    let file = fs::File::open(file_name)
-        .expect("File not found");
-    let reader = BufReader::new(file);
-
-    let matrix = parse_matrix(reader)?;
-    let mut is_visible = empty_matrix(matrix.len() as u32, 0);
-    let mut scenic_scores = empty_matrix(matrix.len() as u32, (0, 0, 0, 0));
-
-    part_one(&matrix, &mut is_visible);
-    part_two(&matrix, &mut scenic_scores);
+use crate::days::arg_or;
 
 
+// Rectangular grid of tree heights, stored row major
+struct Forest {
+    width: usize,
+    height: usize,
+    heights: Vec<u32>,
+}
 
-    // print matrix and get number visible 
-    let mut visible = 0;
+#[derive(Clone, Copy)]
+enum Side {
+    Left = 0,
+    Right = 1,
+    Top = 2,
+    Bottom = 3,
+}
 
-    for i in 0..is_visible.len() {
-        for j in 0..is_visible[0].len() { 
-            print!("{}", is_visible[i][j]);
-            if is_visible[i][j] > 0 {
-                visible += 1;
-            }
-        }
-        println!();
-    }
+#[derive(Clone, Copy, Default)]
+struct TreeView {
+    // Bit set of the sides this tree can be seen from
+    visible_from: u8,
+    // Number of trees seen when looking towards each side
+    distances: [u32; 4],
+}
 
-    println!();
+struct Analysis {
+    width: usize,
+    views: Vec<TreeView>,
+}
 
 
-    let mut max_score = 0;
+pub fn run(file_name: &str) -> std::io::Result<()> {
+    let file = fs::File::open(file_name)
+        .expect("File not found");
+    let reader = BufReader::new(file);
 
-    for i in 0..scenic_scores.len() {
-        for j in 0..scenic_scores[0].len() { 
-            let val = scenic_scores[i][j];
-            let score = val.0 * val.1 * val.2 * val.3;
+    let forest = parse_forest(reader)?;
+    let analysis = forest.analyse();
 
-            print!("({} {} {} {})", val.0, val.1, val.2, val.3);
-            if score > max_score {
-                max_score = score;
+    // Usage: cargo run -- 8 [top N | heatmap [sides | scenic]]
+    match arg_or(2, String::new()).as_str() {
+        "top" => {
+            for (row, col, score) in analysis.top_scenic(arg_or(3, 10)) {
+                println!("({}, {}) height={} score={}",
+                    row, col, forest.get(row, col), score);
             }
-        }
-        println!();
+        },
+        "heatmap" => match arg_or(3, String::from("sides")).as_str() {
+            "scenic" => print!("{}", analysis.scenic_heatmap()),
+            _ => print!("{}", analysis.sides_heatmap()),
+        },
+        _ => {
+            println!("visible: {}", analysis.visible_count());
+            println!("max score: {}", analysis.top_scenic(1)
+                .first()
+                .map(|t| t.2)
+                .unwrap_or(0));
+        },
     }
 
-    println!("visible: {}", visible);
-    println!("max score: {}", max_score);
-
     Ok(())
 }
 
 
+/* Analysis */
+impl Forest {
+    fn get(&self, row: usize, col: usize) -> u32 {
+        self.heights[row * self.width + col]
+    }
 
-fn part_one(matrix: &Vec<Vec<u32>>, is_visible: &mut Vec<Vec<u32>>) {
-    for row_i in 0..matrix.len() {
-        let row = &matrix[row_i];
-        let len = row.len();
-        let mut high = row[0];
-        
-        // left to right check 
-        is_visible[row_i][0] += 1;
-        for col_i in 1..len {
-            let height = row[col_i];
-            if height > high {
-                high = height;
-                is_visible[row_i][col_i] += 1;
-            }
-        }
+    fn analyse(&self) -> Analysis {
+        let mut views = vec![TreeView::default(); self.width * self.height];
 
-        // right to left check
-        is_visible[row_i][len - 1] += 1;
-        high = row[len - 1];
+        for row in 0..self.height {
+            let mut line: Vec<usize> = (0..self.width)
+                .map(|col| row * self.width + col)
+                .collect();
+            self.scan_line(&line, Side::Left, &mut views);
+            line.reverse();
+            self.scan_line(&line, Side::Right, &mut views);
+        }
 
-        for i in 1..(len + 1) {
-            let col_i = len - i; 
-            let height = row[col_i];
-            if height > high {
-                high = height;
-                is_visible[row_i][col_i] += 1;
-            }
+        for col in 0..self.width {
+            let mut line: Vec<usize> = (0..self.height)
+                .map(|row| row * self.width + col)
+                .collect();
+            self.scan_line(&line, Side::Top, &mut views);
+            line.reverse();
+            self.scan_line(&line, Side::Bottom, &mut views);
         }
+
+        Analysis { width: self.width, views }
     }
 
-    // Check cols 
-    for col_i in 0..matrix.len() {
-        let len = matrix[0].len();
-        let mut high = matrix[0][col_i];
-        
-        // left to right check 
-        is_visible[0][col_i] += 1;
-        for row_i in 1..len {
-            let height = matrix[row_i][col_i];
-            if height > high {
-                high = height;
-                is_visible[row_i][col_i] += 1;
+    // Walks a line of trees starting from the given side, keeping a stack
+    // of non increasing heights. Whatever is left on the stack after
+    // popping shorter trees is the first tree blocking the view that way
+    fn scan_line(&self, line: &[usize], side: Side, views: &mut [TreeView]) {
+        let mut stack: Vec<usize> = Vec::new();
+
+        for (i, index) in line.iter().enumerate() {
+            let height = self.heights[*index];
+
+            while let Some(top) = stack.last() {
+                if self.heights[line[*top]] >= height {
+                    break;
+                }
+                stack.pop();
             }
-        }
 
-        // right to left check
-        is_visible[len - 1][col_i] += 1;
+            let view = &mut views[*index];
 
-        for i in 1..(len + 1) {
-            let row_i = len - i; 
-            let height = matrix[row_i][col_i];
-            if height > high {
-                high = height;
-                is_visible[row_i][col_i] += 1;
+            match stack.last() {
+                Some(top) => view.distances[side as usize] = (i - top) as u32,
+                None => {
+                    // Nothing blocks the view so can see the edge
+                    view.distances[side as usize] = i as u32;
+                    view.visible_from |= 1 << side as u8;
+                },
             }
+
+            stack.push(i);
         }
     }
 }
 
 
-fn part_two(matrix: &Vec<Vec<u32>>, scenic_scores: &mut Vec<Vec<(u32, u32, u32, u32)>>) {
-    let len = matrix.len();
+/* Queries */
+impl TreeView {
+    fn scenic_score(&self) -> u64 {
+        self.distances.iter().map(|d| *d as u64).product()
+    }
+}
 
-    for row_i in 0..len {
-        for col_i in 0..len {
-            let val = matrix[row_i][col_i]; 
-            let score = &mut scenic_scores[row_i][col_i];
+impl Analysis {
+    fn visible_count(&self) -> usize {
+        self.views.iter().filter(|v| v.visible_from != 0).count()
+    }
 
-            // Check left 
-            for row_i in (0..row_i).rev() {
-                score.0 += 1;
-                if matrix[row_i][col_i] >= val { break; }
-            }
+    // Highest scoring trees as (row, col, score), best first
+    fn top_scenic(&self, n: usize) -> Vec<(usize, usize, u64)> {
+        let mut scores: Vec<(usize, usize, u64)> = self.views.iter()
+            .enumerate()
+            .map(|(i, v)| (i / self.width, i % self.width, v.scenic_score()))
+            .collect();
 
-            // Check right 
-            for row_i in (row_i + 1)..len {
-                score.1 += 1;
-                if matrix[row_i][col_i] >= val { break; }
-            }
+        scores.sort_by_key(|s| std::cmp::Reverse(s.2));
+        scores.truncate(n);
+        scores
+    }
 
-            // Check up 
-            for col_i in (0..col_i).rev() {
-                score.2 += 1;
-                if matrix[row_i][col_i] >= val { break; }
-            }
+    // One hex digit per tree, each bit being a side it is visible from
+    // (1 left, 2 right, 4 top, 8 bottom)
+    fn sides_heatmap(&self) -> String {
+        self.heatmap(|v| v.visible_from as u64, 15)
+    }
 
-            // Check down
-            for col_i in (col_i + 1)..len {
-                score.3 += 1;
-                if matrix[row_i][col_i] >= val { break; }
-            }
-        }
+    // Scenic scores scaled to 0-9 relative to the best tree
+    fn scenic_heatmap(&self) -> String {
+        let max = self.views.iter().map(|v| v.scenic_score()).max().unwrap_or(0);
+        self.heatmap(|v| v.scenic_score(), max)
     }
-}
 
+    fn heatmap<F: Fn(&TreeView) -> u64>(&self, value: F, max: u64) -> String {
+        let digits = if max <= 15 { max.max(1) } else { 9 };
+        let mut output = String::new();
 
-fn empty_matrix<T: std::clone::Clone + Copy>(size: u32, init_val: T) -> Vec<Vec<T>> {
-    let mut out = Vec::new();
+        for (i, view) in self.views.iter().enumerate() {
+            let scaled = value(view) * digits / max.max(1);
+            output.push(std::char::from_digit(scaled as u32, 16).unwrap());
 
-    for _ in 0..size {
-        let mut line = Vec::with_capacity(size.try_into().unwrap());
-        line.resize(size.try_into().unwrap(), init_val);
-        out.push(line);
-    }
+            if (i + 1) % self.width == 0 {
+                output.push('\n');
+            }
+        }
 
-    out
+        output
+    }
 }
 
 
-fn parse_matrix<R>(reader: BufReader<R>) -> std::io::Result<Vec<Vec<u32>>> where R: std::io::Read {
-    let mut out = Vec::new();
+/* Parsing */
+fn parse_forest<R>(reader: BufReader<R>) -> std::io::Result<Forest> where R: std::io::Read {
+    let mut heights = Vec::new();
+    let mut width = 0;
+    let mut height = 0;
 
     for l in reader.lines() {
         let line = l?;
-        out.push(parse_matrix_line(&line));
-    }
-
-    Ok(out)
-}
+        if line.is_empty() {
+            continue;
+        }
 
+        let row = parse_forest_line(&line);
 
-fn parse_matrix_line(line: &str) -> Vec<u32> {
-    let mut out = Vec::new();
+        if height > 0 && row.len() != width {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("row {} has {} trees, expected {}", height + 1, row.len(), width)
+            ));
+        }
 
-    for ch in line.chars() {
-        out.push(ch as u32 - '0' as u32);
+        width = row.len();
+        height += 1;
+        heights.extend(row);
     }
 
-    return out;
-}
\ No newline at end of file
+    Ok(Forest { width, height, heights })
+}
+
+
+fn parse_forest_line(line: &str) -> Vec<u32> {
+    line.chars()
+        .map(|ch| ch as u32 - '0' as u32)
+        .collect()
+}
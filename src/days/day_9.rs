@@ -4,6 +4,7 @@ use std::io::prelude::*;
 use std::collections::HashSet;
 
 use crate::d9::MovementDirection::*;
+use crate::days::arg_or;
 
 
 struct Movement {
@@ -11,11 +12,23 @@ struct Movement {
     amount: u32,
 }
 
+#[derive(Clone, Copy)]
 enum MovementDirection {
     Right,
     Left,
     Up,
     Down,
+    UpRight,
+    UpLeft,
+    DownRight,
+    DownLeft,
+}
+
+// Rope of any number of knots, knot 0 being the head. Every knot keeps
+// the set of positions it has visited
+struct Rope {
+    knots: Vec<(i32, i32)>,
+    visited: Vec<HashSet<(i32, i32)>>,
 }
 
 
@@ -23,117 +36,163 @@ pub fn run(file_name: &str) -> std::io::Result<()> {
     let file = fs::File::open(file_name)
         .expect("File not found");
     let reader = BufReader::new(file);
-    
-    let rope_length = 10 as usize;
-    let mut rope_positions = Vec::<(i32, i32)>::new();
 
-    for _ in 0..rope_length {
-        rope_positions.push((0, 0));
+    // Usage: cargo run -- 9 [knots] [trail KNOT]
+    let rope_length = arg_or(2, 10usize).max(1);
+    let mut rope = Rope::new(rope_length);
+
+    for (i, l) in reader.lines().enumerate() {
+        let line = l?;
+        let movements = parse_script(&line)
+            .unwrap_or_else(|| panic!("Invalid movement on line {}: {}", i + 1, line));
+
+        for movement in movements {
+            rope.apply(&movement);
+        }
     }
 
-    let mut visited_tail_pos = HashSet::<(i32, i32)>::new();
+    for knot in 0..rope_length {
+        println!("knot {}: {}", knot, rope.visited[knot].len());
+    }
 
-    visited_tail_pos.insert(rope_positions[rope_length - 1]);
+    if rope_length > 1 {
+        // Knots only follow the one in front so the first two knots
+        // behave exactly like a rope of length 2
+        println!("part one: {}", rope.visited[1].len());
+    }
+    println!("part two: {}", rope.visited[rope_length - 1].len());
+
+    if arg_or(3, String::new()) == "trail" {
+        let knot = arg_or(4, rope_length - 1).min(rope_length - 1);
+        print!("{}", rope.render_trail(knot));
+    }
 
-    for l in reader.lines() {
-        let movement = parse_movement(&l?).unwrap();
+    Ok(())
+}
 
-        movement.print();
 
+/* Simulation */
+impl Rope {
+    fn new(length: usize) -> Rope {
+        let mut visited = vec![HashSet::new(); length];
+
+        for v in visited.iter_mut() {
+            v.insert((0, 0));
+        }
+
+        Rope {
+            knots: vec![(0, 0); length],
+            visited,
+        }
+    }
+
+    fn apply(&mut self, movement: &Movement) {
         for _ in 0..movement.amount {
-            update_head_pos(&movement.direction, &mut rope_positions[0]);
-            print!("h:({}, {})", rope_positions[0].0, rope_positions[0].1);
-            
-            for i in 1..rope_length {
-                let prev = rope_positions[i - 1];
-                update_tail_pos(&prev, &mut rope_positions[i]);
-
-                print!(" - t{}({}, {})", i, rope_positions[i].0, rope_positions[i].1);
-            }  
-
-            println!();
-            visited_tail_pos.insert(rope_positions[rope_length - 1]);
+            self.step(movement.direction);
         }
     }
 
-    println!("solution: {}", visited_tail_pos.len());
+    fn step(&mut self, dir: MovementDirection) {
+        update_head_pos(dir, &mut self.knots[0]);
+        self.visited[0].insert(self.knots[0]);
+
+        for i in 1..self.knots.len() {
+            let prev = self.knots[i - 1];
+            if !update_tail_pos(&prev, &mut self.knots[i]) {
+                // Rest of the rope can't move either
+                break;
+            }
+            self.visited[i].insert(self.knots[i]);
+        }
+    }
 
-    Ok(())
+    // Draws the positions visited by a knot, 's' marking the start
+    fn render_trail(&self, knot: usize) -> String {
+        let trail = &self.visited[knot];
+        let min_x = trail.iter().map(|p| p.0).min().unwrap_or(0);
+        let max_x = trail.iter().map(|p| p.0).max().unwrap_or(0);
+        let min_y = trail.iter().map(|p| p.1).min().unwrap_or(0);
+        let max_y = trail.iter().map(|p| p.1).max().unwrap_or(0);
+
+        let mut output = String::new();
+
+        for y in (min_y..=max_y).rev() {
+            for x in min_x..=max_x {
+                output.push(match (x, y) {
+                    (0, 0) => 's',
+                    pos if trail.contains(&pos) => '#',
+                    _ => '.',
+                });
+            }
+            output.push('\n');
+        }
+
+        output
+    }
 }
 
-fn update_head_pos(dir: &MovementDirection, head_pos: &mut (i32, i32)) {
-    match dir {
-        Right => head_pos.0 += 1,
-        Left  => head_pos.0 -= 1,
-        Up    => head_pos.1 += 1,
-        Down  => head_pos.1 -= 1,
+fn update_head_pos(dir: MovementDirection, head_pos: &mut (i32, i32)) {
+    let (dx, dy) = match dir {
+        Right     => (1, 0),
+        Left      => (-1, 0),
+        Up        => (0, 1),
+        Down      => (0, -1),
+        UpRight   => (1, 1),
+        UpLeft    => (-1, 1),
+        DownRight => (1, -1),
+        DownLeft  => (-1, -1),
     };
+
+    head_pos.0 += dx;
+    head_pos.1 += dy;
 }
 
-fn update_tail_pos(head_pos: &(i32, i32), tail_pos: &mut (i32, i32)) {
-    if (head_pos.0 - tail_pos.0).abs() <= 1 && 
+// Returns whether the tail had to move
+fn update_tail_pos(head_pos: &(i32, i32), tail_pos: &mut (i32, i32)) -> bool {
+    if (head_pos.0 - tail_pos.0).abs() <= 1 &&
        (head_pos.1 - tail_pos.1).abs() <= 1 {
-        return;
-    }
-
-    fn get_modified(h: i32, t: i32) -> i32 {
-        if h < t { -1 }
-        else if h > t { 1 }
-        else { 0 }
+        return false;
     }
 
-    tail_pos.0 += get_modified(head_pos.0, tail_pos.0);
-    tail_pos.1 += get_modified(head_pos.1, tail_pos.1);
+    tail_pos.0 += (head_pos.0 - tail_pos.0).signum();
+    tail_pos.1 += (head_pos.1 - tail_pos.1).signum();
+    true
 }
 
 
 
-/* Debug */
-impl Movement {
-    fn print(&self) {
-        print!("== ");
-
-        match self.direction {
-            Right => print!("R"),
-            Left  => print!("L"),
-            Up    => print!("U"),
-            Down  => print!("D"),
-        }
-
-        println!(" {} ==", self.amount);
-    }
+/* Parsing */
+// A line holds one or more movements separated by ',' or ';',
+// e.g. "R 4" or "UR 2, D 3"
+fn parse_script(line: &str) -> Option<Vec<Movement>> {
+    line.split([',', ';'])
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+        .map(parse_movement)
+        .collect()
 }
 
 
-/* Parsing */
-fn parse_movement(line: &str) -> Option<Movement> {
+fn parse_movement(movement: &str) -> Option<Movement> {
+    let (dir, amount) = movement.split_once(' ')?;
+
     Some(Movement {
-        direction: parse_movement_dir(line)?,
-        amount: parse_movement_amount(line),
+        direction: parse_movement_dir(dir)?,
+        amount: amount.trim().parse().ok()?,
     })
 }
 
 
-fn parse_movement_dir(line: &str) -> Option<MovementDirection> {
-    match line.chars().next() {
-        Some('R') => Some(Right),
-        Some('L') => Some(Left),
-        Some('U') => Some(Up),
-        Some('D') => Some(Down),
+fn parse_movement_dir(dir: &str) -> Option<MovementDirection> {
+    match dir {
+        "R" => Some(Right),
+        "L" => Some(Left),
+        "U" => Some(Up),
+        "D" => Some(Down),
+        "UR" | "RU" => Some(UpRight),
+        "UL" | "LU" => Some(UpLeft),
+        "DR" | "RD" => Some(DownRight),
+        "DL" | "LD" => Some(DownLeft),
         _ => None
     }
 }
-
-fn parse_movement_amount(line: &str) -> u32 {
-    let mut output = 0;
-
-    for ch in line.chars().skip(2) {
-        if !('0'..='9').contains(&ch) {
-            break;
-        }
-
-        output = (output * 10) +  (ch as u32 - '0' as u32);
-    }
-
-    output
-}
\ No newline at end of file
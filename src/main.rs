@@ -28,7 +28,8 @@ use days::day_25 as d25;
 
 
 fn main() {
-    let day = 25;
+    // Usage: cargo run -- [day] [day specific options...]
+    let day = days::arg_or(1, 25);
 
     match day {
         1 => d1::run("./src/input/day_1.txt").expect("Failed to run"),